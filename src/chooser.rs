@@ -0,0 +1,66 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use xcb_util::ewmh;
+
+// Run a chooser (dmenu, rofi -dmenu, ...) in a new thread, feeding it items on stdin.
+// The helper thread can't touch our connection, so the chosen line is handed to the
+// callback along with a fresh one: it reports back through EWMH messages / properties
+pub fn spawn<F>(args: &'static [&str], items: Vec<String>, callback: F) where F: FnOnce(&ewmh::Connection, i32, &str) + Send + 'static {
+    thread::spawn(move || {
+        // Log
+        debug!("Running chooser: {:?}", args);
+
+        // Start the chooser with piped stdin / stdout
+        let mut child = match Command::new(args[0]).args(args.iter().skip(1)).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                warn!("{:?}: {}", args, err);
+                return;
+            },
+        };
+
+        // Write items, then close stdin so it knows the list is complete
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Err(err) = stdin.write_all(items.join("\n").as_bytes()) {
+                warn!("{:?}: {}", args, err);
+            }
+        }
+
+        // Wait for the choice. Nothing chosen (e.g. escape pressed) is fine
+        let output = match child.wait_with_output() {
+            Ok(output) => output,
+            Err(err) => {
+                warn!("{:?}: {}", args, err);
+                return;
+            },
+        };
+        let chosen = String::from_utf8_lossy(&output.stdout);
+        let chosen = chosen.lines().next().unwrap_or_default();
+        if chosen.is_empty() {
+            debug!("{:?}: nothing chosen", args);
+            return;
+        }
+
+        // Open our own connection to report the choice back with
+        let (conn, screen_idx) = match xcb::Connection::connect(None) {
+            Ok(conn) => conn,
+            Err(err) => {
+                warn!("Chooser failed to connect: {}", err);
+                return;
+            },
+        };
+        let conn = match ewmh::Connection::connect(conn) {
+            Ok(conn) => conn,
+            Err(_) => {
+                warn!("Chooser failed to get EWMH connection");
+                return;
+            },
+        };
+
+        // Report, making sure it's sent before the connection closes
+        callback(&conn, screen_idx, chosen);
+        conn.flush();
+    });
+}
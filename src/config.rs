@@ -1,25 +1,91 @@
+use crate::chooser;
+use crate::helper;
 use crate::layout::LayoutType;
-use crate::wm::WM;
+use crate::rules::Rule;
+use crate::windows::Direction;
+use crate::wm::{FocusModel, Keybind, MouseAction, MouseContext, Mousebind, WM};
+use crate::x::{XBackend, XWindowID};
 
 use std::process::Command;
 use std::thread;
+use std::time::Duration;
 
 use xcb::xproto;
+use xcb_util::ewmh;
 use x11::keysym;
 
 // Geometry
 pub const WIN_WIDTH_MIN: u16 = 200;
 pub const WIN_HEIGHT_MIN: u16 = 100;
 
-// Number of workspaces to have
-pub const WORKSPACES: usize = 9;
+// Pixel steps for keyboard driven window move / resize
+pub const KEYBOARD_MOVE_STEP: i32 = 20;
+pub const KEYBOARD_RESIZE_STEP: i32 = 20;
+
+// Distance in pixels at which dragged windows snap to screen / window edges
+pub const SNAP_DISTANCE: i32 = 15;
+
+// Show a geometry readout while moving / resizing windows with the mouse
+pub const SHOW_DRAG_GEOMETRY: bool = true;
+
+// Minimum time between window updates while dragging, roughly one frame at 60Hz.
+// Set to zero to update on every (compressed) motion event
+pub const DRAG_UPDATE_INTERVAL: Duration = Duration::from_millis(16);
+
+// How the mouse moves focus between windows
+pub const FOCUS_MODEL: FocusModel = FocusModel::Sloppy;
+
+// When focus follows the mouse, raise the focused window after this long.
+// None never raises, zero raises immediately
+pub const AUTO_RAISE_DELAY: Option<Duration> = None;
+
+// Workspaces to start with, by name. More can be created / destroyed at runtime
+pub const WORKSPACE_NAMES: &[&str] = &["1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+// Most workspaces a pager may ask for via _NET_NUMBER_OF_DESKTOPS, larger requests are ignored
+pub const MAX_WORKSPACES: usize = 64;
+
+// Number of previously visited workspaces remembered for going back
+pub const WORKSPACE_HISTORY: usize = 32;
+
+// Going to the current workspace by index goes back to the previous one instead
+pub const AUTO_BACK_AND_FORTH: bool = false;
+
+// Pass over workspaces without windows when going to the next / previous
+pub const SKIP_EMPTY_WORKSPACES: bool = false;
+
+// Chooser prompting for a new workspace name, given the current one on stdin
+pub const WORKSPACE_RENAME_CHOOSER: &[&str] = &["dmenu", "-p", "Rename workspace:", "-fn", "Ubuntu Mono:size=12", "-nb", "#1d1f21", "-nf", "#66d9ef", "-sb", "#1d1f21", "-sf", "#66d9ef"];
+
+// Chooser picking a window to switch to, given lines of: id [workspace] class: title
+pub const WINDOW_SWITCH_CHOOSER: &[&str] = &["dmenu", "-i", "-l", "10", "-p", "Window:", "-fn", "Ubuntu Mono:size=12", "-nb", "#1d1f21", "-nf", "#66d9ef", "-sb", "#1d1f21", "-sf", "#66d9ef"];
+
+// Named scratchpads of tuple: (name, command spawning it). Commands should set a
+// WM_CLASS matched by a scratchpad rule below, an empty command is filled by sending a window
+pub const SCRATCHPADS: &[(&str, &[&str])] = &[
+    ("term",    &["alacritty", "--class", "scratchterm"]),
+    ("notes",   &["alacritty", "--class", "scratchnotes", "-e", "nvim", "notes.md"]),
+    ("scratch", &[]),
+];
+
+// Window rules of tuple: (WM_CLASS instance or class name, rule)
+pub const RULES: &[(&str, Rule)] = &[
+    ("scratchterm",  Rule::Scratchpad("term")),
+    ("scratchnotes", Rule::Scratchpad("notes")),
+    ("xclock",       Rule::Sticky),
+];
 
 // Modifier key for keybinds
 pub const MODKEY: u32 = xproto::MOD_MASK_4;
 
 // Key binds of tuple: (mask, key, function)
-pub const KEYBINDS: &[(xcb::ModMask, xcb::Keysym, fn(&mut WM))] = &[
+pub const KEYBINDS: &[Keybind] = &[
+    // Keybinding modes
+    (MODKEY, keysym::XK_r, |wm|{ wm.mode_enter("resize") }),
+    (MODKEY, keysym::XK_a, |wm|{ wm.mode_enter("chord") }),
+
     // Dmenu
+    (MODKEY, keysym::XK_w, |wm|{ switch_window(wm) }),
     (MODKEY, keysym::XK_p, |_|{ run(&["dmenu_run", "-fn", "Ubuntu Mono:size=12", "-nb", "#1d1f21", "-nf", "#66d9ef", "-sb", "#1d1f21", "-sf", "#66d9ef"]) }),
 
     // Backlight keys
@@ -43,19 +109,66 @@ pub const KEYBINDS: &[(xcb::ModMask, xcb::Keysym, fn(&mut WM))] = &[
 
     // Switch focused window
     (MODKEY, keysym::XK_Tab, |wm| { wm.desktop.current_mut().window_focus_cycle(&wm.conn, &wm.screen) }),
+    (xproto::MOD_MASK_1, keysym::XK_Tab, |wm| { wm.desktop.current_mut().window_focus_previous(&wm.conn, &wm.screen) }),
+
+    // Focus window in direction
+    (MODKEY, keysym::XK_h, |wm|{ wm.desktop.current_mut().window_focus_direction(&wm.conn, &wm.screen, Direction::Left) }),
+    (MODKEY, keysym::XK_j, |wm|{ wm.desktop.current_mut().window_focus_direction(&wm.conn, &wm.screen, Direction::Down) }),
+    (MODKEY, keysym::XK_k, |wm|{ wm.desktop.current_mut().window_focus_direction(&wm.conn, &wm.screen, Direction::Up) }),
+    (MODKEY, keysym::XK_l, |wm|{ wm.desktop.current_mut().window_focus_direction(&wm.conn, &wm.screen, Direction::Right) }),
+
+    // Swap focused window with window in direction
+    (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_h, |wm|{ wm.desktop.current_mut().window_swap_direction(&wm.conn, &wm.screen, Direction::Left) }),
+    (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_j, |wm|{ wm.desktop.current_mut().window_swap_direction(&wm.conn, &wm.screen, Direction::Down) }),
+    (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_k, |wm|{ wm.desktop.current_mut().window_swap_direction(&wm.conn, &wm.screen, Direction::Up) }),
+    (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_l, |wm|{ wm.desktop.current_mut().window_swap_direction(&wm.conn, &wm.screen, Direction::Right) }),
+
+    // Move focused window
+    (MODKEY|xproto::MOD_MASK_CONTROL, keysym::XK_Left,  |wm|{ move_focused_window(wm, -KEYBOARD_MOVE_STEP, 0) }),
+    (MODKEY|xproto::MOD_MASK_CONTROL, keysym::XK_Right, |wm|{ move_focused_window(wm,  KEYBOARD_MOVE_STEP, 0) }),
+    (MODKEY|xproto::MOD_MASK_CONTROL, keysym::XK_Up,    |wm|{ move_focused_window(wm, 0, -KEYBOARD_MOVE_STEP) }),
+    (MODKEY|xproto::MOD_MASK_CONTROL, keysym::XK_Down,  |wm|{ move_focused_window(wm, 0,  KEYBOARD_MOVE_STEP) }),
+
+    // Resize focused window
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_Left,  |wm|{ resize_focused_window(wm, -KEYBOARD_RESIZE_STEP, 0) }),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_Right, |wm|{ resize_focused_window(wm,  KEYBOARD_RESIZE_STEP, 0) }),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_Up,    |wm|{ resize_focused_window(wm, 0, -KEYBOARD_RESIZE_STEP) }),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_Down,  |wm|{ resize_focused_window(wm, 0,  KEYBOARD_RESIZE_STEP) }),
+
+    // Snap focused window to screen edge
+    (MODKEY|xproto::MOD_MASK_1, keysym::XK_Left,  |wm|{ snap_focused_window(wm, Direction::Left) }),
+    (MODKEY|xproto::MOD_MASK_1, keysym::XK_Right, |wm|{ snap_focused_window(wm, Direction::Right) }),
+    (MODKEY|xproto::MOD_MASK_1, keysym::XK_Up,    |wm|{ snap_focused_window(wm, Direction::Up) }),
+    (MODKEY|xproto::MOD_MASK_1, keysym::XK_Down,  |wm|{ snap_focused_window(wm, Direction::Down) }),
+
+    // Centre focused window
+    (MODKEY, keysym::XK_c, |wm|{ centre_focused_window(wm) }),
+
+    // Minimize focused window, restore the most recently minimized on this workspace
+    (MODKEY, keysym::XK_m, |wm|{ minimize_focused_window(wm) }),
+    (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_m, |wm|{ restore_minimized_window(wm) }),
+
+    // Toggle focused window shown on all workspaces
+    (MODKEY, keysym::XK_s, |wm|{ toggle_sticky_focused_window(wm) }),
 
     // Workspace switching
-    (MODKEY, keysym::XK_1, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, 0) }),
-    (MODKEY, keysym::XK_2, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, 1) }),
-    (MODKEY, keysym::XK_3, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, 2) }),
-    (MODKEY, keysym::XK_4, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, 3) }),
-    (MODKEY, keysym::XK_5, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, 4) }),
-    (MODKEY, keysym::XK_6, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, 5) }),
-    (MODKEY, keysym::XK_7, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, 6) }),
-    (MODKEY, keysym::XK_8, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, 7) }),
-    (MODKEY, keysym::XK_9, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, 8) }),
-    (MODKEY, keysym::XK_Left,  |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, wm.desktop.index_prev()) }),
-    (MODKEY, keysym::XK_Right, |wm|{ wm.desktop.goto(&wm.conn, &wm.screen, wm.desktop.index_next()) }),
+    (MODKEY, keysym::XK_1, |wm|{ goto_workspace(wm, 0) }),
+    (MODKEY, keysym::XK_2, |wm|{ goto_workspace(wm, 1) }),
+    (MODKEY, keysym::XK_3, |wm|{ goto_workspace(wm, 2) }),
+    (MODKEY, keysym::XK_4, |wm|{ goto_workspace(wm, 3) }),
+    (MODKEY, keysym::XK_5, |wm|{ goto_workspace(wm, 4) }),
+    (MODKEY, keysym::XK_6, |wm|{ goto_workspace(wm, 5) }),
+    (MODKEY, keysym::XK_7, |wm|{ goto_workspace(wm, 6) }),
+    (MODKEY, keysym::XK_8, |wm|{ goto_workspace(wm, 7) }),
+    (MODKEY, keysym::XK_9, |wm|{ goto_workspace(wm, 8) }),
+    (MODKEY, keysym::XK_Left,  |wm|{ wm.workspace_goto(wm.desktop.index_prev()) }),
+    (MODKEY, keysym::XK_Right, |wm|{ wm.workspace_goto(wm.desktop.index_next()) }),
+    (MODKEY, keysym::XK_BackSpace, |wm|{ wm.workspace_goto_previous() }),
+
+    // Workspace creating / destroying / renaming
+    (MODKEY|xproto::MOD_MASK_CONTROL, keysym::XK_n,         |wm|{ let idx = wm.workspace_create(""); wm.workspace_goto(idx) }),
+    (MODKEY|xproto::MOD_MASK_CONTROL, keysym::XK_BackSpace, |wm|{ wm.workspace_destroy(wm.desktop.index()) }),
+    (MODKEY|xproto::MOD_MASK_CONTROL, keysym::XK_r,         |wm|{ rename_current_workspace(wm) }),
 
     // Sending windows to workspaces
     (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_1, |wm|{ send_window_from_workspace_to(wm, 0) } ),
@@ -70,10 +183,71 @@ pub const KEYBINDS: &[(xcb::ModMask, xcb::Keysym, fn(&mut WM))] = &[
     (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_Left,  |wm|{ send_window_from_workspace_to(wm, wm.desktop.index_prev()) } ),
     (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_Right, |wm|{ send_window_from_workspace_to(wm, wm.desktop.index_next()) } ),
 
+    // Sending windows to workspaces, following them there
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_1, |wm|{ send_window_and_follow(wm, 0) } ),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_2, |wm|{ send_window_and_follow(wm, 1) } ),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_3, |wm|{ send_window_and_follow(wm, 2) } ),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_4, |wm|{ send_window_and_follow(wm, 3) } ),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_5, |wm|{ send_window_and_follow(wm, 4) } ),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_6, |wm|{ send_window_and_follow(wm, 5) } ),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_7, |wm|{ send_window_and_follow(wm, 6) } ),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_8, |wm|{ send_window_and_follow(wm, 7) } ),
+    (MODKEY|xproto::MOD_MASK_CONTROL|xproto::MOD_MASK_SHIFT, keysym::XK_9, |wm|{ send_window_and_follow(wm, 8) } ),
+
+    // Scratchpads
+    (MODKEY, keysym::XK_grave, |wm|{ toggle_scratchpad(wm, "term") }),
+    (MODKEY, keysym::XK_n,     |wm|{ toggle_scratchpad(wm, "notes") }),
+    (MODKEY, keysym::XK_minus, |wm|{ toggle_scratchpad(wm, "scratch") }),
+    (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_minus, |wm|{ wm.scratchpads.send(&mut wm.desktop, &wm.conn, &wm.screen, "scratch") }),
+
     // Set current workspace window layout
     (MODKEY|xproto::MOD_MASK_SHIFT, keysym::XK_f, |wm|{ wm.desktop.current_mut().set_layout(&wm.conn, &wm.screen, LayoutType::Floating) } ),
 ];
 
+// Mouse binds of tuple: (mask, button, context, action). Buttons 4 and 5 are scroll up and down
+pub const MOUSEBINDS: &[Mousebind] = &[
+    // Drag windows
+    (MODKEY, xcb::BUTTON_INDEX_1, MouseContext::Client, MouseAction::Move),
+    (MODKEY, xcb::BUTTON_INDEX_3, MouseContext::Client, MouseAction::Resize),
+
+    // Close or lower window
+    (MODKEY,                        xcb::BUTTON_INDEX_2, MouseContext::Client, MouseAction::Call(close_window)),
+    (MODKEY|xproto::MOD_MASK_SHIFT, xcb::BUTTON_INDEX_2, MouseContext::Client, MouseAction::Call(lower_window)),
+
+    // Switch workspaces
+    (MODKEY, xcb::BUTTON_INDEX_4, MouseContext::Root, MouseAction::Call(|wm, _|{ wm.workspace_goto(wm.desktop.index_prev()) })),
+    (MODKEY, xcb::BUTTON_INDEX_5, MouseContext::Root, MouseAction::Call(|wm, _|{ wm.workspace_goto(wm.desktop.index_next()) })),
+];
+
+// Keybinding modes of tuple: (name, leave after the next key?, key binds). Escape returns to the default mode.
+// One-shot modes ignore MODKEY, so their binds work whether or not it's still held
+pub const MODES: &[(&str, bool, &[Keybind])] = &[
+    ("resize", false, RESIZE_KEYBINDS),
+    ("chord",  true,  CHORD_KEYBINDS),
+];
+
+// Resize mode: bare keys resize / move the focused window until Escape
+const RESIZE_KEYBINDS: &[Keybind] = &[
+    (0, keysym::XK_h, |wm|{ resize_focused_window(wm, -KEYBOARD_RESIZE_STEP, 0) }),
+    (0, keysym::XK_j, |wm|{ resize_focused_window(wm, 0,  KEYBOARD_RESIZE_STEP) }),
+    (0, keysym::XK_k, |wm|{ resize_focused_window(wm, 0, -KEYBOARD_RESIZE_STEP) }),
+    (0, keysym::XK_l, |wm|{ resize_focused_window(wm,  KEYBOARD_RESIZE_STEP, 0) }),
+    (xproto::MOD_MASK_SHIFT, keysym::XK_h, |wm|{ move_focused_window(wm, -KEYBOARD_MOVE_STEP, 0) }),
+    (xproto::MOD_MASK_SHIFT, keysym::XK_j, |wm|{ move_focused_window(wm, 0,  KEYBOARD_MOVE_STEP) }),
+    (xproto::MOD_MASK_SHIFT, keysym::XK_k, |wm|{ move_focused_window(wm, 0, -KEYBOARD_MOVE_STEP) }),
+    (xproto::MOD_MASK_SHIFT, keysym::XK_l, |wm|{ move_focused_window(wm,  KEYBOARD_MOVE_STEP, 0) }),
+    (0, keysym::XK_Return, |wm|{ wm.mode_leave() }),
+];
+
+// Chord mode: one bare key after MOD+a
+const CHORD_KEYBINDS: &[Keybind] = &[
+    (0, keysym::XK_t, |_|{ run(&["alacritty"]) }),
+    (0, keysym::XK_c, |wm|{ centre_focused_window(wm) }),
+    (0, keysym::XK_s, |wm|{ toggle_sticky_focused_window(wm) }),
+    (0, keysym::XK_m, |wm|{ minimize_focused_window(wm) }),
+    (0, keysym::XK_n, |wm|{ let idx = wm.workspace_create(""); wm.workspace_goto(idx) }),
+];
+
 // If there is a currently focused window, send a kill client command via X
 fn close_focused_window(wm: &mut WM) {
     if let Some(focused) = wm.desktop.current_mut().windows.focused() {
@@ -81,11 +255,160 @@ fn close_focused_window(wm: &mut WM) {
     }
 }
 
+// If window is managed on the current workspace, send a kill client command via X
+fn close_window(wm: &mut WM, window_id: XWindowID) {
+    if let Some(idx) = wm.desktop.current().windows.contains(window_id) {
+        wm.conn.destroy_window(wm.desktop.current().windows.get(idx).unwrap());
+    }
+}
+
+// Push window to the bottom of the stack
+fn lower_window(wm: &mut WM, window_id: XWindowID) {
+    if wm.desktop.current().windows.contains(window_id).is_some() {
+        wm.conn.configure_window(window_id, &helper::values_configure_stack_below());
+    }
+}
+
+// If there is a currently focused window, move it by dx, dy
+fn move_focused_window(wm: &mut WM, dx: i32, dy: i32) {
+    if let Some(focused) = wm.desktop.current_mut().windows.focused_mut() {
+        focused.do_move(&wm.conn, &wm.screen, dx, dy);
+    }
+}
+
+// If there is a currently focused window, resize it by dx, dy
+fn resize_focused_window(wm: &mut WM, dx: i32, dy: i32) {
+    if let Some(focused) = wm.desktop.current_mut().windows.focused_mut() {
+        focused.do_resize(&wm.conn, &wm.screen, dx, dy);
+    }
+}
+
+// If there is a currently focused window, snap it to screen edge in direction
+fn snap_focused_window(wm: &mut WM, direction: Direction) {
+    if let Some(focused) = wm.desktop.current_mut().windows.focused_mut() {
+        focused.do_snap(&wm.conn, &wm.screen, direction);
+    }
+}
+
+// If there is a currently focused window, centre it on screen
+fn centre_focused_window(wm: &mut WM) {
+    if let Some(focused) = wm.desktop.current_mut().windows.focused_mut() {
+        focused.do_centre(&wm.conn, &wm.screen);
+    }
+}
+
+// If there is a currently focused window, minimize it
+fn minimize_focused_window(wm: &mut WM) {
+    if let Some(focused) = wm.desktop.current().windows.focused() {
+        let window_id = focused.xwindow.id;
+        wm.window_minimize(window_id);
+    }
+}
+
+// If there are minimized windows on the current workspace, restore the most recent. Repeat to cycle back through them
+fn restore_minimized_window(wm: &mut WM) {
+    if let Some(hidden) = wm.desktop.current().hidden.last() {
+        let window_id = hidden.xwindow.id;
+        wm.window_restore(window_id);
+    }
+}
+
+// If there is a currently focused window, toggle it shown on all workspaces
+fn toggle_sticky_focused_window(wm: &mut WM) {
+    if let Some(focused) = wm.desktop.current().windows.focused() {
+        let (window_id, sticky) = (focused.xwindow.id, focused.sticky);
+        wm.set_sticky(window_id, !sticky);
+    }
+}
+
+// Go to workspace at index, or back to the previous one if already there (when enabled)
+fn goto_workspace(wm: &mut WM, idx: usize) {
+    if AUTO_BACK_AND_FORTH && idx == wm.desktop.index() {
+        wm.workspace_goto_previous();
+    } else {
+        wm.workspace_goto(idx);
+    }
+}
+
 // If there is a currently focused window, sends from current workspace to workspace at index
 fn send_window_from_workspace_to(wm: &mut WM, idx: usize) {
+    // Nowhere to send it
+    if idx >= wm.desktop.len() || idx == wm.desktop.index() {
+        return;
+    }
+
+    // Sending a window somewhere in particular unsticks it
+    if let Some(focused) = wm.desktop.current().windows.focused() {
+        if focused.sticky {
+            let window_id = focused.xwindow.id;
+            wm.set_sticky(window_id, false);
+        }
+    }
+
     if let Some(focused) = wm.desktop.current_mut().window_del_focused(&wm.conn, &wm.screen) {
         // Remove this window from current workspace
-        wm.desktop.get_mut(idx).windows.add(focused);
+        let window_id = focused.xwindow.id;
+        wm.desktop.window_move_to(&wm.conn, &wm.screen, focused, idx);
+        wm.update_wm_desktop(window_id);
+    }
+}
+
+// If there is a currently focused window, sends it to workspace at index and goes there too
+fn send_window_and_follow(wm: &mut WM, idx: usize) {
+    if wm.desktop.current().windows.focused().is_some() && idx < wm.desktop.len() {
+        send_window_from_workspace_to(wm, idx);
+        wm.workspace_goto(idx);
+    }
+}
+
+// Prompt for a new name for the current workspace. The chooser sets _NET_DESKTOP_NAMES,
+// which we pick up in WM::on_property_notify()
+fn rename_current_workspace(wm: &mut WM) {
+    let idx = wm.desktop.index();
+    let name = wm.desktop.names()[idx].to_owned();
+    chooser::spawn(WORKSPACE_RENAME_CHOOSER, vec![name.clone()], move |conn, screen_idx, chosen| {
+        // Workspaces may have changed while the prompt was open, so work from the names as they are now
+        let mut names: Vec<String> = match ewmh::get_desktop_names(conn, screen_idx).get_reply() {
+            Ok(reply) => reply.strings().iter().map(|name| name.to_string()).collect(),
+            Err(_) => return,
+        };
+
+        // Only rename if the workspace is still where it was
+        if names.get(idx) != Some(&name) {
+            warn!("Workspace {} moved while renaming, not renaming", name);
+            return;
+        }
+        names[idx] = chosen.to_owned();
+        ewmh::set_desktop_names(conn, screen_idx, names.iter().map(String::as_str));
+    });
+}
+
+// Pick any window, from all workspaces and minimized, then activate it via _NET_ACTIVE_WINDOW
+fn switch_window(wm: &mut WM) {
+    let mut items = Vec::new();
+    for ws in wm.desktop.iter() {
+        for window in ws.windows.iter().chain(ws.hidden.iter()) {
+            let id = window.xwindow.id;
+            let (_, class) = wm.conn.get_wm_class(id).unwrap_or_default();
+            let title = wm.conn.get_wm_name(id).unwrap_or_default();
+
+            // One line per window, so no line breaks within
+            let line = format!("{:#x} [{}] {}: {}", id, ws.name, class, title);
+            items.push(line.replace(|c| c == '\n' || c == '\r', " "));
+        }
+    }
+    chooser::spawn(WINDOW_SWITCH_CHOOSER, items, |conn, screen_idx, chosen| {
+        let id = chosen.split(' ').next().unwrap_or_default().trim_start_matches("0x");
+        if let Ok(id) = XWindowID::from_str_radix(id, 16) {
+            ewmh::request_change_active_window(conn, screen_idx, id, ewmh::CLIENT_SOURCE_TYPE_OTHER, xcb::CURRENT_TIME, xcb::NONE);
+        }
+    });
+}
+
+// Show / hide the named scratchpad, spawning it if not running
+fn toggle_scratchpad(wm: &mut WM, name: &str) {
+    if let Some(command) = wm.scratchpads.toggle(&mut wm.desktop, &wm.conn, &wm.screen, name) {
+        run(command);
     }
 }
 
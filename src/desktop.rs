@@ -1,48 +1,166 @@
-use crate::config::WORKSPACES;
+use crate::config::{SKIP_EMPTY_WORKSPACES, WORKSPACE_HISTORY, WORKSPACE_NAMES};
 use crate::screen::Screen;
+use crate::windows::Window;
 use crate::workspace::Workspace;
-use crate::x::{XConn, XWindowID};
+use crate::x::{XBackend, XWindowID};
 
-#[derive(Default)]
 pub struct Desktop {
     // Internal workspace tracking
-    workspaces: [Workspace; WORKSPACES],
+    workspaces: Vec<Workspace>,
 
     // Current workspace index
     idx: usize,
+
+    // Previously visited workspace indices, most recent last
+    history: Vec<usize>,
+}
+
+impl Default for Desktop {
+    fn default() -> Self {
+        let mut workspaces: Vec<Workspace> = WORKSPACE_NAMES.iter().map(|name| Workspace::named(name)).collect();
+
+        // First workspace starts on-screen
+        workspaces[0].active = true;
+
+        Self {
+            workspaces: workspaces,
+            idx: 0,
+            history: Vec::new(),
+        }
+    }
 }
 
 impl Desktop {
+    pub fn len(&self) -> usize {
+        return self.workspaces.len();
+    }
+
+    pub fn index(&self) -> usize {
+        return self.idx;
+    }
+
     pub fn index_next(&self) -> usize {
-        if self.idx < WORKSPACES-1 {
-            return self.idx + 1;
-        } else {
-            return 0;
-        }
+        return self.index_step(1);
     }
 
     pub fn index_prev(&self) -> usize {
-        if self.idx > 0 {
-            return self.idx - 1;
-        } else {
-            return WORKSPACES - 1;
+        return self.index_step(self.workspaces.len() - 1);
+    }
+
+    fn index_step(&self, step: usize) -> usize {
+        // Wrap around the live workspaces, optionally passing over those without windows
+        let mut idx = self.idx;
+        for _ in 1..self.workspaces.len() {
+            idx = (idx + step) % self.workspaces.len();
+            if !SKIP_EMPTY_WORKSPACES || !self.workspaces[idx].windows.is_empty() {
+                return idx;
+            }
         }
+        return self.idx;
     }
 
-    pub fn goto(&mut self, conn: &XConn, screen: &Screen, idx: usize) {
+    pub fn goto(&mut self, conn: &dyn XBackend, screen: &Screen, idx: usize) {
+        // Ignore workspaces that don't exist (yet), or where we already are
+        if idx >= self.workspaces.len() {
+            debug!("Goto non-existent workspace: {}", idx);
+            return;
+        }
+        if idx == self.idx {
+            return;
+        }
+
         // Log
         debug!("Goto workspace: {}", idx);
 
-        // Deactivate current selected workspace
+        // Deactivate current selected workspace, taking sticky windows with us
         self.workspaces.get_mut(self.idx).unwrap().deactivate(conn);
+        let sticky = self.workspaces.get_mut(self.idx).unwrap().windows.take_sticky();
 
-        // Update index
+        // Update index, remembering where we came from
+        self.history.push(self.idx);
+        if self.history.len() > WORKSPACE_HISTORY {
+            self.history.remove(0);
+        }
         self.idx = idx;
 
+        // Sticky windows go behind those already here, so focus stays put
+        for window in sticky {
+            self.workspaces.get_mut(self.idx).unwrap().windows.add_back(window);
+        }
+
         // Activate newly selected workspace
         self.workspaces.get_mut(self.idx).unwrap().activate(conn, screen);
     }
 
+    pub fn goto_previous(&mut self, conn: &dyn XBackend, screen: &Screen) {
+        // Going back records where we are, so repeating toggles between the two
+        if let Some(idx) = self.history.pop() {
+            self.goto(conn, screen, idx);
+        }
+    }
+
+    pub fn create(&mut self, name: &str) -> usize {
+        // Unnamed workspaces are just numbered
+        let name = if name.is_empty() { (self.workspaces.len() + 1).to_string() } else { name.to_owned() };
+        debug!("Creating workspace: {}", name);
+
+        self.workspaces.push(Workspace::named(&name));
+        return self.workspaces.len() - 1;
+    }
+
+    pub fn destroy(&mut self, conn: &dyn XBackend, screen: &Screen, idx: usize) -> bool {
+        // Always keep at least one workspace
+        if idx >= self.workspaces.len() || self.workspaces.len() == 1 {
+            return false;
+        }
+        debug!("Destroying workspace: {}", idx);
+
+        // Windows move to the previous workspace (or next, if first). Leave if it's current
+        let target = if idx == 0 { 1 } else { idx - 1 };
+        if idx == self.idx {
+            self.goto(conn, screen, target);
+        }
+
+        // Remove, shifting indices down (history included)
+        let ws = self.workspaces.remove(idx);
+        if self.idx > idx {
+            self.idx -= 1;
+        }
+        self.history.retain(|i| *i != idx);
+        for i in self.history.iter_mut().filter(|i| **i > idx) {
+            *i -= 1;
+        }
+        self.history.dedup();
+
+        // Going back to where we already are would make goto_previous() do nothing
+        let current = self.idx;
+        self.history.retain(|i| *i != current);
+        let target = if target > idx { target - 1 } else { target };
+
+        // Hand over its windows, keeping their order
+        for window in ws.windows.iter_rev() {
+            self.window_move_to(conn, screen, window.to_owned(), target);
+        }
+        self.workspaces.get_mut(target).unwrap().hidden.extend(ws.hidden);
+        return true;
+    }
+
+    pub fn rename(&mut self, idx: usize, name: &str) {
+        if let Some(ws) = self.workspaces.get_mut(idx) {
+            debug!("Renaming workspace {}: {}", idx, name);
+            ws.name = name.to_owned();
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        return self.workspaces.iter().map(|ws| ws.name.as_str()).collect();
+    }
+
+    pub fn window_move_to(&mut self, conn: &dyn XBackend, screen: &Screen, window: Window, idx: usize) {
+        // Through the target layout, which holds off mapping if it's not on-screen
+        self.workspaces.get_mut(idx).unwrap().window_add(conn, screen, window);
+    }
+
     pub fn current(&self) -> &Workspace {
         return self.workspaces.get(self.idx).unwrap();
     }
@@ -59,6 +177,45 @@ impl Desktop {
         return self.workspaces.get_mut(idx).unwrap();
     }
 
+    pub fn index_of(&self, window_id: XWindowID) -> Option<usize> {
+        // Minimized windows still belong to a workspace
+        return self.workspaces.iter().position(|ws| ws.windows.contains(window_id).is_some() || ws.hidden.iter().any(|w| w.xwindow.id == window_id));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Workspace> {
+        return self.workspaces.iter();
+    }
+
+    pub fn window(&self, window_id: XWindowID) -> Option<&Window> {
+        // Search both shown and minimized windows
+        for ws in self.workspaces.iter() {
+            if let Some(idx) = ws.windows.index_of(window_id) {
+                return ws.windows.get(idx);
+            }
+            if let Some(window) = ws.hidden.iter().find(|w| w.xwindow.id == window_id) {
+                return Some(window);
+            }
+        }
+        return None;
+    }
+
+    pub fn window_mut(&mut self, window_id: XWindowID) -> Option<&mut Window> {
+        // Search both shown and minimized windows
+        for ws in self.workspaces.iter_mut() {
+            if let Some(idx) = ws.windows.index_of(window_id) {
+                return ws.windows.get_mut(idx);
+            }
+            if let Some(window) = ws.hidden.iter_mut().find(|w| w.xwindow.id == window_id) {
+                return Some(window);
+            }
+        }
+        return None;
+    }
+
+    pub fn hidden_index_of(&self, window_id: XWindowID) -> Option<usize> {
+        return self.workspaces.iter().position(|ws| ws.hidden.iter().any(|w| w.xwindow.id == window_id));
+    }
+
     pub fn contains(&self, window_id: XWindowID) -> Option<(&Workspace, usize)> {
         for ws in self.workspaces.iter() {
             if let Some(idx) = ws.windows.index_of(window_id) {
@@ -77,3 +234,120 @@ impl Desktop {
         return None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Desktop;
+    use crate::mock::{self, RecordingBackend, Request};
+
+    #[test]
+    fn goto_keeps_sticky_windows_mapped_and_carries_them() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut desktop = Desktop::default();
+        let mut sticky = mock::window(2, 0, 0, 200, 100);
+        sticky.sticky = true;
+        desktop.current_mut().window_add(&conn, &screen, sticky);
+        desktop.current_mut().window_add(&conn, &screen, mock::window(3, 0, 0, 200, 100));
+        desktop.get_mut(1).window_add(&conn, &screen, mock::window(4, 0, 0, 200, 100));
+        conn.take();
+
+        desktop.goto(&conn, &screen, 1);
+
+        // Only the non-sticky window is unmapped, focus stays with the new workspace
+        assert!(!conn.take_visible().contains(&Request::Unmap(2)));
+        assert!(desktop.get(0).windows.contains(2).is_none());
+        assert_eq!(desktop.current().windows.iter().map(|w| w.xwindow.id).collect::<Vec<_>>(), vec![4, 2]);
+        assert_eq!(desktop.index_of(2), Some(1));
+    }
+
+    #[test]
+    fn index_next_prev_wrap_over_live_workspaces() {
+        let mut desktop = Desktop::default();
+        let last = desktop.len() - 1;
+
+        assert_eq!(desktop.index_prev(), last);
+        assert_eq!(desktop.index_next(), 1);
+
+        let created = desktop.create("");
+        assert_eq!(desktop.index_prev(), created);
+        assert_eq!(desktop.names()[created], (created + 1).to_string());
+    }
+
+    #[test]
+    fn destroy_hands_windows_to_previous_workspace() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut desktop = Desktop::default();
+        let len = desktop.len();
+        desktop.get_mut(2).windows.add(mock::window(2, 0, 0, 200, 100));
+        desktop.goto(&conn, &screen, 3);
+        conn.take();
+
+        assert!(desktop.destroy(&conn, &screen, 2));
+
+        // Current workspace shifted down with the removal
+        assert_eq!(desktop.len(), len - 1);
+        assert_eq!(desktop.index(), 2);
+        assert_eq!(desktop.index_of(2), Some(1));
+        assert!(conn.take_visible().is_empty());
+    }
+
+    #[test]
+    fn destroy_current_goes_to_previous_and_maps_its_windows() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut desktop = Desktop::default();
+        desktop.goto(&conn, &screen, 1);
+        desktop.current_mut().window_add(&conn, &screen, mock::window(2, 0, 0, 200, 100));
+        conn.take();
+
+        assert!(desktop.destroy(&conn, &screen, 1));
+
+        assert_eq!(desktop.index(), 0);
+        assert_eq!(desktop.current().windows.focused().unwrap().xwindow.id, 2);
+        assert!(conn.take_visible().contains(&Request::Map(2)));
+    }
+
+    #[test]
+    fn destroy_current_keeps_goto_previous_moving() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut desktop = Desktop::default();
+        desktop.goto(&conn, &screen, 1);
+        desktop.goto(&conn, &screen, 2);
+
+        // Destroying lands on 1, so going back must skip it
+        assert!(desktop.destroy(&conn, &screen, 2));
+        assert_eq!(desktop.index(), 1);
+        desktop.goto_previous(&conn, &screen);
+        assert_eq!(desktop.index(), 0);
+    }
+
+    #[test]
+    fn goto_previous_toggles_and_same_index_is_ignored() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut desktop = Desktop::default();
+        desktop.current_mut().window_add(&conn, &screen, mock::window(2, 0, 0, 200, 100));
+        conn.take();
+
+        // Going where we already are does nothing
+        desktop.goto(&conn, &screen, 0);
+        assert!(conn.take().is_empty());
+
+        // So there's nowhere to go back to
+        desktop.goto_previous(&conn, &screen);
+        assert_eq!(desktop.index(), 0);
+        assert!(conn.take().is_empty());
+
+        desktop.goto(&conn, &screen, 4);
+        desktop.goto(&conn, &screen, 2);
+        desktop.goto_previous(&conn, &screen);
+        assert_eq!(desktop.index(), 4);
+        desktop.goto_previous(&conn, &screen);
+        assert_eq!(desktop.index(), 2);
+        desktop.goto_previous(&conn, &screen);
+        assert_eq!(desktop.index(), 4);
+    }
+}
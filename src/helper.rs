@@ -28,6 +28,12 @@ pub fn values_configure_stack_above() -> [(u16, u32); 1] {
     return [(xcb::CONFIG_WINDOW_STACK_MODE as u16, xcb::STACK_MODE_ABOVE)];
 }
 
+// Values array of configuration setting window at bottom of stack
+pub fn values_configure_stack_below() -> [(u16, u32); 1] {
+    debug!("VALUES: configure stack below");
+    return [(xcb::CONFIG_WINDOW_STACK_MODE as u16, xcb::STACK_MODE_BELOW)];
+}
+
 // Values array of attributes setting the cursor to use
 pub fn values_attributes_cursor(cursor_id: u32) -> [(u32, u32); 1] {
     debug!("VALUES: attributes cursor");
@@ -39,7 +45,9 @@ pub fn values_attributes_root() -> [(u32, u32); 1] {
     debug!("VALUES: attributes root");
     return [(xcb::CW_EVENT_MASK,
         xcb::EVENT_MASK_SUBSTRUCTURE_REDIRECT| // -> Child window CirculateRequest, ConfigureRequest, MapRequest events
-        xcb::EVENT_MASK_STRUCTURE_NOTIFY       // -> Self CirculateNotify, ConfigureNotify, DestroyNotify, GravityNotify, MapNotify, ReparentNotify, UnmapNotify events
+        xcb::EVENT_MASK_STRUCTURE_NOTIFY|      // -> Self CirculateNotify, ConfigureNotify, DestroyNotify, GravityNotify, MapNotify, ReparentNotify, UnmapNotify events
+        xcb::EVENT_MASK_PROPERTY_CHANGE|       // -> Self PropertyNotify events (pagers setting desktop names)
+        xcb::EVENT_MASK_ENTER_WINDOW           // -> Self EnterNotify events (pointer leaving windows for the root)
     )];
 }
 
@@ -52,6 +60,40 @@ pub fn values_attributes_child_events() -> [(u32, u32); 1] {
     )];
 }
 
+// Values array of attributes for our selection owner check window
+pub fn values_attributes_check_window() -> [(u32, u32); 2] {
+    debug!("VALUES: attributes check window");
+    return [
+        (xcb::CW_OVERRIDE_REDIRECT, 1),                        // -> Never generate MapRequest events for this window
+        (xcb::CW_EVENT_MASK, xcb::EVENT_MASK_PROPERTY_CHANGE), // -> Self PropertyNotify events (used for timestamps)
+    ];
+}
+
+// Values array of attributes for our drag geometry readout window
+pub fn values_attributes_readout_window(background: u32, border: u32) -> [(u32, u32); 4] {
+    debug!("VALUES: attributes readout window");
+    return [
+        (xcb::CW_BACK_PIXEL, background),               // -> Background colour
+        (xcb::CW_BORDER_PIXEL, border),                 // -> Border colour
+        (xcb::CW_OVERRIDE_REDIRECT, 1),                 // -> Never generate MapRequest events for this window
+        (xcb::CW_EVENT_MASK, xcb::EVENT_MASK_EXPOSURE), // -> Self Expose events (redrawing when uncovered)
+    ];
+}
+
+// Values array of configuration setting window position ontop of stack
+pub fn values_configure_move_stack_above(x: u32, y: u32) -> [(u16, u32); 3] {
+    debug!("VALUES: configure move stack above");
+    return [(xcb::CONFIG_WINDOW_X as u16, x), (xcb::CONFIG_WINDOW_Y as u16, y), (xcb::CONFIG_WINDOW_STACK_MODE as u16, xcb::STACK_MODE_ABOVE)];
+}
+
+// Values array of attributes setting structure event mask only
+pub fn values_attributes_structure_events() -> [(u32, u32); 1] {
+    debug!("VALUES: attributes structure events");
+    return [(xcb::CW_EVENT_MASK,
+        xcb::EVENT_MASK_STRUCTURE_NOTIFY // -> Self CirculateNotify, ConfigureNotify, DestroyNotify, GravityNotify, MapNotify, ReparentNotify, UnmapNotify events
+    )];
+}
+
 // Values array of attributes setting no event mask
 pub fn values_attributes_no_events() -> [(u32, u32); 1] {
     debug!("VALUES: attributes no events");
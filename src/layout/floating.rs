@@ -2,9 +2,11 @@ use crate::helper;
 use crate::screen::Screen;
 use crate::windows::Window;
 use crate::workspace::Workspace;
-use crate::x::{XConn, XWindowID};
+use crate::x::{XBackend, XWindowID};
 
-pub fn activate(ws: &mut Workspace, conn: &XConn, screen: &Screen) {
+use xcb_util::icccm;
+
+pub fn activate(ws: &mut Workspace, conn: &dyn XBackend, screen: &Screen) {
     // If empty, this is pointless
     if ws.windows.is_empty() {
         return;
@@ -14,29 +16,45 @@ pub fn activate(ws: &mut Workspace, conn: &XConn, screen: &Screen) {
     for window in ws.windows.iter_rev() {
         // Map the window to the display
         conn.map_window(window.xwindow.id);
+        conn.set_icccm_wm_state(window.xwindow.id, icccm::WM_STATE_NORMAL);
     }
 
-    // Tell X to focus our focused window
-    conn.set_input_focus(ws.windows.focused().unwrap().xwindow.id);
+    // Tell X to focus our focused window, or the root window if focus was dropped
+    match ws.windows.focused() {
+        Some(window) => conn.set_input_focus(window.xwindow.id),
+        None => conn.set_input_focus(screen.xwindow.id),
+    }
 }
 
-pub fn deactivate(ws: &mut Workspace, conn: &XConn) {
+pub fn deactivate(ws: &mut Workspace, conn: &dyn XBackend) {
     // Iterate windows
-    for window in ws.windows.iter() {
-        // Disable events before unmapping the window
-        conn.change_window_attributes(window.xwindow.id, &helper::values_attributes_no_events());
+    for window in ws.windows.iter_mut() {
+        // Sticky windows stay on screen
+        if window.sticky {
+            continue;
+        }
 
-        // Unmap the window
+        // Unmap the window, expecting the resulting UnmapNotify
+        window.expected_unmaps += 1;
         conn.unmap_window(window.xwindow.id);
 
-        // Enable events again
-        conn.change_window_attributes(window.xwindow.id, &helper::values_attributes_child_events());
+        // Still managed, just not on-screen
+        conn.set_icccm_wm_state(window.xwindow.id, icccm::WM_STATE_ICONIC);
     }
 }
 
-pub fn window_add(ws: &mut Workspace, conn: &XConn, screen: &Screen, window: Window) {
+pub fn window_add(ws: &mut Workspace, conn: &dyn XBackend, screen: &Screen, window: Window) {
+    // Off-screen workspaces just track the window until activated
+    if !ws.active {
+        conn.change_window_attributes(window.xwindow.id, &helper::values_attributes_child_events());
+        conn.set_icccm_wm_state(window.xwindow.id, icccm::WM_STATE_ICONIC);
+        ws.windows.add(window);
+        return;
+    }
+
     // Tell X to map and focus the window
     conn.map_window(window.xwindow.id);
+    conn.set_icccm_wm_state(window.xwindow.id, icccm::WM_STATE_NORMAL);
 
     // Start tracking events for this window
     conn.change_window_attributes(window.xwindow.id, &helper::values_attributes_child_events());
@@ -51,40 +69,44 @@ pub fn window_add(ws: &mut Workspace, conn: &XConn, screen: &Screen, window: Win
     ws.windows.add(window);
 }
 
-pub fn window_del(ws: &mut Workspace, conn: &XConn, screen: &Screen, idx: usize, window_id: XWindowID) -> Window {
+pub fn window_del(ws: &mut Workspace, conn: &dyn XBackend, screen: &Screen, idx: usize, window_id: XWindowID) -> Window {
     // Get window and own_
-    let window = ws.windows.get(idx).unwrap().to_owned();
+    let mut window = ws.windows.get(idx).unwrap().to_owned();
 
     // Internally remove window at position
     ws.windows.remove(idx);
 
-    // Stop tracking events for this window
-    conn.change_window_attributes(window_id, &helper::values_attributes_no_events());
-
-    // Tell X to unmap the window
-    conn.unmap_window(window_id);
+    // Tell X to unmap the window if on-screen, expecting the resulting UnmapNotify
+    if ws.active {
+        window.expected_unmaps += 1;
+        conn.unmap_window(window_id);
+    }
 
-    // If we just deleted the previously focused, try focus the next index 0
-    if idx == 0 {
-        if let Some(window) = ws.windows.get(0) { window_input_focus_set_ontop(conn, window.xwindow.id); }
+    // If we just deleted the previously focused, try focus the next index 0 (if on-screen and focus wasn't dropped)
+    if idx == 0 && ws.active {
+        if let Some(window) = ws.windows.focused() { window_input_focus_set_ontop(conn, window.xwindow.id); }
     }
 
     // Return the Window
     return window;
 }
 
-pub fn window_focus(ws: &mut Workspace, conn: &XConn, screen: &Screen, window_id: XWindowID) {
+pub fn window_focus(ws: &mut Workspace, conn: &dyn XBackend, screen: &Screen, window_id: XWindowID, raise: bool) {
     // Focus window (if there!)
     if let Some(idx) = ws.windows.index_of(window_id) {
         // Internally, move to front
         ws.windows.move_front(idx);
 
-        // Focus input + set ontop
-        window_input_focus_set_ontop(conn, window_id);
+        // Focus input + set ontop, or leave stacking alone
+        if raise {
+            window_input_focus_set_ontop(conn, window_id);
+        } else {
+            conn.set_input_focus(window_id);
+        }
     }
 }
 
-pub fn window_focus_cycle(ws: &mut Workspace, conn: &XConn, screen: &Screen) {
+pub fn window_focus_cycle(ws: &mut Workspace, conn: &dyn XBackend, screen: &Screen) {
     // Get length just the once
     let len = ws.windows.len();
 
@@ -96,23 +118,299 @@ pub fn window_focus_cycle(ws: &mut Workspace, conn: &XConn, screen: &Screen) {
     // Internally, move last window to front
     ws.windows.move_front(len-1);
 
-    // Get window in question
-    let window = ws.windows.get(len-1).unwrap();
+    // Get window in question, now at the front
+    let window = ws.windows.get(0).unwrap();
 
     // Focus input + set ontop
     window_input_focus_set_ontop(conn, window.xwindow.id);
 }
 
-fn window_input_focus_set_ontop(conn: &XConn, window_id: XWindowID) {
-    // Disable event tracking before making changes
-    conn.change_window_attributes(window_id, &helper::values_attributes_no_events());
+pub fn window_swap(ws: &mut Workspace, conn: &dyn XBackend, screen: &Screen, idx_a: usize, idx_b: usize) {
+    // Take copies of both window geometries
+    let a = ws.windows.get(idx_a).unwrap().xwindow.clone();
+    let b = ws.windows.get(idx_b).unwrap().xwindow.clone();
+
+    // Floating windows swap places by exchanging geometries
+    for (idx, geometry) in [(idx_a, &b), (idx_b, &a)].iter() {
+        let window = ws.windows.get_mut(*idx).unwrap();
+        window.xwindow.x = geometry.x;
+        window.xwindow.y = geometry.y;
+        window.xwindow.width = geometry.width;
+        window.xwindow.height = geometry.height;
+
+        // Send new window configuration to X
+        conn.configure_window(window.xwindow.id, &helper::values_configure_geometry(geometry.x as u32, geometry.y as u32, geometry.width as u32, geometry.height as u32));
+    }
+}
 
+fn window_input_focus_set_ontop(conn: &dyn XBackend, window_id: XWindowID) {
     // Set window ontop
     conn.configure_window(window_id, &helper::values_configure_stack_above());
 
     // Tell X to focus the window
     conn.set_input_focus(window_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::helper;
+    use crate::mock::{self, RecordingBackend, Request};
+    use crate::windows::Direction;
+    use crate::workspace::Workspace;
+
+    use xcb_util::icccm;
+
+    fn workspace_with(conn: &RecordingBackend, ids: &[u32]) -> Workspace {
+        let screen = mock::screen();
+        let mut ws = Workspace::default();
+        ws.active = true;
+        for id in ids {
+            ws.window_add(conn, &screen, mock::window(*id, 0, 0, 200, 100));
+        }
+        conn.take();
+        return ws;
+    }
+
+    fn focused_id(ws: &Workspace) -> u32 {
+        return ws.windows.focused().unwrap().xwindow.id;
+    }
+
+    #[test]
+    fn window_add_maps_raises_and_focuses() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[]);
+
+        ws.window_add(&conn, &mock::screen(), mock::window(2, 0, 0, 200, 100));
+
+        assert_eq!(conn.take_visible(), vec![
+            Request::Map(2),
+            Request::Configure(2, helper::values_configure_stack_above().to_vec()),
+            Request::Focus(2),
+        ]);
+        assert_eq!(focused_id(&ws), 2);
+    }
+
+    #[test]
+    fn window_focus_raises_and_moves_to_front() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3]);
+
+        ws.window_focus(&conn, &mock::screen(), 2);
+
+        assert_eq!(conn.take_visible(), vec![
+            Request::Configure(2, helper::values_configure_stack_above().to_vec()),
+            Request::Focus(2),
+        ]);
+        assert_eq!(focused_id(&ws), 2);
+    }
+
+    #[test]
+    fn window_focus_no_raise_only_focuses() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3]);
+
+        ws.window_focus_no_raise(&conn, &mock::screen(), 2);
+
+        assert_eq!(conn.take_visible(), vec![Request::Focus(2)]);
+        assert_eq!(focused_id(&ws), 2);
+    }
+
+    #[test]
+    fn window_focus_cycle_focuses_last_window() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3, 4]);
+
+        ws.window_focus_cycle(&conn, &mock::screen());
+
+        assert_eq!(conn.take_visible(), vec![
+            Request::Configure(2, helper::values_configure_stack_above().to_vec()),
+            Request::Focus(2),
+        ]);
+        assert_eq!(focused_id(&ws), 2);
+    }
+
+    #[test]
+    fn window_focus_keeps_most_recently_used_order() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3, 4, 5]);
+
+        ws.window_focus(&conn, &mock::screen(), 3);
+
+        let order: Vec<u32> = ws.windows.iter().map(|w| w.xwindow.id).collect();
+        assert_eq!(order, vec![3, 5, 4, 2]);
+    }
+
+    #[test]
+    fn window_focus_previous_toggles() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3, 4]);
+
+        ws.window_focus_previous(&conn, &mock::screen());
+        assert_eq!(focused_id(&ws), 3);
+        ws.window_focus_previous(&conn, &mock::screen());
+        assert_eq!(focused_id(&ws), 4);
+    }
 
-    // Enable event tracking again
-    conn.change_window_attributes(window_id, &helper::values_attributes_child_events());
-}
\ No newline at end of file
+    #[test]
+    fn window_swap_direction_exchanges_geometry() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut ws = Workspace::default();
+        ws.window_add(&conn, &screen, mock::window(2, 1000, 0, 300, 300));
+        ws.window_add(&conn, &screen, mock::window(3, 0, 0, 200, 200));
+        conn.take();
+
+        ws.window_swap_direction(&conn, &screen, Direction::Right);
+
+        assert_eq!(conn.take(), vec![
+            Request::Configure(3, helper::values_configure_geometry(1000, 0, 300, 300).to_vec()),
+            Request::Configure(2, helper::values_configure_geometry(0, 0, 200, 200).to_vec()),
+        ]);
+        assert_eq!(focused_id(&ws), 3);
+    }
+
+    #[test]
+    fn window_del_focused_focuses_next() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3]);
+
+        let removed = ws.window_del_focused(&conn, &mock::screen()).unwrap();
+
+        assert_eq!(removed.xwindow.id, 3);
+        assert_eq!(conn.take_visible(), vec![
+            Request::Unmap(3),
+            Request::Configure(2, helper::values_configure_stack_above().to_vec()),
+            Request::Focus(2),
+        ]);
+        assert_eq!(ws.windows.len(), 1);
+    }
+
+    #[test]
+    fn wm_state_follows_workspace_activation() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[]);
+
+        ws.window_add(&conn, &mock::screen(), mock::window(2, 0, 0, 200, 100));
+        assert!(conn.take().contains(&Request::WmState(2, icccm::WM_STATE_NORMAL)));
+
+        ws.deactivate(&conn);
+        assert!(conn.take().contains(&Request::WmState(2, icccm::WM_STATE_ICONIC)));
+
+        ws.activate(&conn, &mock::screen());
+        assert!(conn.take().contains(&Request::WmState(2, icccm::WM_STATE_NORMAL)));
+    }
+
+    #[test]
+    fn deactivate_activate_unmaps_then_maps_all() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3]);
+
+        ws.deactivate(&conn);
+        assert_eq!(conn.take_visible(), vec![Request::Unmap(3), Request::Unmap(2)]);
+
+        ws.activate(&conn, &mock::screen());
+        assert_eq!(conn.take_visible(), vec![Request::Map(2), Request::Map(3), Request::Focus(3)]);
+    }
+
+    #[test]
+    fn window_add_to_inactive_workspace_only_tracks() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2]);
+        ws.deactivate(&conn);
+        conn.take();
+
+        ws.window_add(&conn, &mock::screen(), mock::window(3, 0, 0, 200, 100));
+        assert_eq!(conn.take_visible(), vec![]);
+
+        ws.activate(&conn, &mock::screen());
+        assert_eq!(conn.take_visible(), vec![Request::Map(2), Request::Map(3), Request::Focus(3)]);
+    }
+
+    #[test]
+    fn window_minimize_hides_and_focuses_next() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3]);
+
+        assert!(ws.window_minimize(&conn, &mock::screen(), 3));
+        assert_eq!(conn.take_visible(), vec![
+            Request::Unmap(3),
+            Request::Configure(2, helper::values_configure_stack_above().to_vec()),
+            Request::Focus(2),
+        ]);
+        assert_eq!(ws.hidden.iter().map(|w| w.xwindow.id).collect::<Vec<_>>(), vec![3]);
+
+        // Minimized windows stay hidden across activation
+        ws.deactivate(&conn);
+        ws.activate(&conn, &mock::screen());
+        assert!(!conn.take_visible().contains(&Request::Map(3)));
+
+        assert!(ws.hidden_take(3).is_some());
+        assert!(ws.hidden.is_empty());
+    }
+
+    #[test]
+    fn unmaps_are_counted_as_expected() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3]);
+
+        ws.deactivate(&conn);
+        assert!(ws.windows.iter().all(|w| w.expected_unmaps == 1));
+        conn.take();
+
+        // Already off-screen, so nothing to unmap or expect
+        let removed = ws.window_del(&conn, &mock::screen(), 0, 3);
+        assert_eq!(removed.expected_unmaps, 1);
+        assert!(!conn.take_visible().contains(&Request::Unmap(3)));
+    }
+
+    #[test]
+    fn only_unfocused_windows_grab_clicks() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2]);
+
+        ws.window_add(&conn, &mock::screen(), mock::window(3, 0, 0, 200, 100));
+        let requests = conn.take();
+        assert!(requests.contains(&Request::GrabClick(2)));
+        assert_eq!(requests.last(), Some(&Request::UngrabClick(3)));
+
+        ws.window_focus(&conn, &mock::screen(), 2);
+        assert_eq!(conn.take().into_iter().filter(|r| match r {
+            Request::GrabClick(..) | Request::UngrabClick(..) => true,
+            _ => false,
+        }).collect::<Vec<_>>(), vec![Request::GrabClick(3), Request::UngrabClick(2)]);
+    }
+
+    #[test]
+    fn window_unfocus_drops_focus_to_root_until_refocused() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut ws = workspace_with(&conn, &[2, 3]);
+
+        ws.window_unfocus(&conn, &screen);
+        assert!(ws.windows.focused().is_none());
+        let requests = conn.take();
+        assert!(requests.contains(&Request::Focus(screen.xwindow.id)));
+        assert!(requests.contains(&Request::GrabClick(3)));
+
+        ws.window_focus(&conn, &screen, 3);
+        assert_eq!(focused_id(&ws), 3);
+    }
+
+    #[test]
+    fn hover_focus_keeps_click_grab_until_raised() {
+        let conn = RecordingBackend::default();
+        let mut ws = workspace_with(&conn, &[2, 3]);
+
+        // Focused by hovering, a click must still reach us to raise it
+        ws.window_focus_no_raise(&conn, &mock::screen(), 2);
+        let requests = conn.take();
+        assert!(requests.contains(&Request::GrabClick(3)));
+        assert!(!requests.contains(&Request::UngrabClick(2)));
+
+        // Clicking then focuses + raises, only now dropping the grab
+        ws.window_focus(&conn, &mock::screen(), 2);
+        let requests = conn.take();
+        assert!(requests.contains(&Request::Configure(2, helper::values_configure_stack_above().to_vec())));
+        assert_eq!(requests.last(), Some(&Request::UngrabClick(2)));
+    }
+}
@@ -1,16 +1,23 @@
 #[macro_use]
 mod log;
 
+mod chooser;
 mod config;
 mod desktop;
 mod helper;
 mod layout;
+mod readout;
+mod rules;
+mod scratchpad;
 mod screen;
 mod windows;
 mod wm;
 mod workspace;
 mod x;
 
+#[cfg(test)]
+mod mock;
+
 use wm::WM;
 
 use std::env;
@@ -29,7 +36,7 @@ fn print_version() {
 
 fn print_usage() {
     println!(
-        "Usage: {} [-h|--help] [-v|--version] [-y|--why]",
+        "Usage: {} [-h|--help] [-v|--version] [-y|--why] [-r|--replace]",
         env!("CARGO_PKG_NAME"),
     )
 }
@@ -38,9 +45,12 @@ fn main() {
     // Get arguments
     let args: Vec<String> = env::args().collect();
 
-    // If arguments provided, either show version or help
-    if args.len() >= 2 {
-        match args.get(1).unwrap().as_str() {
+    // Whether to replace an already running window manager
+    let mut replace = false;
+
+    // If arguments provided, either show version or help, or set options
+    for arg in args.iter().skip(1) {
+        match arg.as_str() {
             "-v"|"--version" => {
                 print_version();
                 process::exit(0);
@@ -56,6 +66,10 @@ fn main() {
                 process::exit(69);
             },
 
+            "-r"|"--replace" => {
+                replace = true;
+            },
+
             _ => {
                 print_usage();
                 process::exit(1);
@@ -75,8 +89,17 @@ fn main() {
     let conn = ewmh::Connection::connect(conn).map_err(|(err, _)| { err }).expect("Failed to get EWMH connection");
 
     // Create new window manager object
-    let mut wm = WM::register(&conn, screen_idx);
+    let mut wm = match WM::register(&conn, screen_idx, replace) {
+        Ok(wm) => wm,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        },
+    };
 
-    // Run window manager!
-    wm.run();
+    // Run window manager! This only returns on a fatal X error
+    if let Err(err) = wm.run() {
+        error!("{}", err);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,121 @@
+use crate::screen::Screen;
+use crate::windows::Window;
+use crate::x::{XBackend, XError, XWindowID, ERROR_BAD_WINDOW};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use xcb_util::icccm;
+
+// A single request as seen by the X server
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Map(XWindowID),
+    Unmap(XWindowID),
+    Configure(XWindowID, Vec<(u16, u32)>),
+    ChangeAttributes(XWindowID, Vec<(u32, u32)>),
+    WmState(XWindowID, icccm::WmState),
+    GrabClick(XWindowID),
+    UngrabClick(XWindowID),
+    Focus(XWindowID),
+    Destroy(XWindowID),
+}
+
+// Backend recording every request in order, for asserting on in tests
+#[derive(Default)]
+pub struct RecordingBackend {
+    requests: RefCell<Vec<Request>>,
+    geometries: HashMap<XWindowID, (i32, i32, i32, i32)>,
+}
+
+impl RecordingBackend {
+    pub fn with_geometry(mut self, window_id: XWindowID, geometry: (i32, i32, i32, i32)) -> Self {
+        self.geometries.insert(window_id, geometry);
+        return self;
+    }
+
+    // Take all recorded requests, clearing the log
+    pub fn take(&self) -> Vec<Request> {
+        return self.requests.replace(Vec::new());
+    }
+
+    // Take only the recorded requests that change what's on screen (no event mask toggling / WM_STATE / grabs)
+    pub fn take_visible(&self) -> Vec<Request> {
+        return self.take().into_iter().filter(|r| match r {
+            Request::ChangeAttributes(..) => false,
+            Request::WmState(..) => false,
+            Request::GrabClick(..) | Request::UngrabClick(..) => false,
+            _ => true,
+        }).collect();
+    }
+
+    fn record(&self, request: Request) {
+        self.requests.borrow_mut().push(request);
+    }
+}
+
+impl XBackend for RecordingBackend {
+    fn map_window(&self, window_id: XWindowID) {
+        self.record(Request::Map(window_id));
+    }
+
+    fn unmap_window(&self, window_id: XWindowID) {
+        self.record(Request::Unmap(window_id));
+    }
+
+    fn configure_window(&self, window_id: XWindowID, values: &[(u16, u32)]) {
+        self.record(Request::Configure(window_id, values.to_vec()));
+    }
+
+    fn change_window_attributes(&self, window_id: XWindowID, values: &[(u32, u32)]) {
+        self.record(Request::ChangeAttributes(window_id, values.to_vec()));
+    }
+
+    fn set_icccm_wm_state(&self, window_id: XWindowID, state: icccm::WmState) {
+        self.record(Request::WmState(window_id, state));
+    }
+
+    fn set_input_focus(&self, window_id: XWindowID) {
+        self.record(Request::Focus(window_id));
+    }
+
+    fn grab_click_to_focus(&self, window_id: XWindowID) {
+        self.record(Request::GrabClick(window_id));
+    }
+
+    fn ungrab_click_to_focus(&self, window_id: XWindowID) {
+        self.record(Request::UngrabClick(window_id));
+    }
+
+    fn destroy_window(&self, window: &Window) {
+        self.record(Request::Destroy(window.xwindow.id));
+    }
+
+    fn get_geometry(&self, window_id: XWindowID) -> Result<(i32, i32, i32, i32), XError> {
+        match self.geometries.get(&window_id) {
+            Some(geometry) => return Ok(*geometry),
+            None => return Err(XError::Protocol { error_code: ERROR_BAD_WINDOW, resource_id: window_id, major_code: 0 }),
+        }
+    }
+
+    fn get_wm_protocols(&self, _window_id: XWindowID) -> Result<Vec<xcb::Atom>, XError> {
+        return Ok(Vec::new());
+    }
+}
+
+// 1920x1080 screen with root window id 1
+pub fn screen() -> Screen {
+    let mut screen = Screen::new(0, 1);
+    screen.xwindow.width = 1920;
+    screen.xwindow.height = 1080;
+    return screen;
+}
+
+// Window with geometry set
+pub fn window(window_id: XWindowID, x: i32, y: i32, width: i32, height: i32) -> Window {
+    let mut window = Window::from(window_id);
+    window.xwindow.x = x;
+    window.xwindow.y = y;
+    window.xwindow.width = width;
+    window.xwindow.height = height;
+    return window;
+}
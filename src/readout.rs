@@ -0,0 +1,78 @@
+use crate::helper;
+use crate::x::{XBackend, XConn, XWindow, XWindowID};
+
+// Readout window size, fits "-9999,-9999 9999x9999" in the fixed font
+const READOUT_WIDTH: u16 = 140;
+const READOUT_HEIGHT: u16 = 18;
+
+// Font used for the readout text
+const READOUT_FONT: &str = "fixed";
+
+// Small override-redirect window showing geometry of a window being dragged
+pub struct Readout {
+    window_id: XWindowID,
+    gc_id: Option<xcb::Gcontext>,
+    visible: bool,
+
+    // Last drawn text, redrawn when uncovered
+    text: String,
+}
+
+impl Readout {
+    pub fn new(conn: &XConn, screen_idx: i32, root_id: XWindowID) -> Self {
+        // Create the readout window + graphics context to draw with
+        let window_id = conn.create_readout_window(screen_idx, root_id, READOUT_WIDTH, READOUT_HEIGHT);
+        let gc_id = match conn.create_text_gc(screen_idx, window_id, READOUT_FONT) {
+            Ok(gc_id) => Some(gc_id),
+            Err(err) => {
+                warn!("Failed creating readout graphics context, disabling: {}", err);
+                None
+            },
+        };
+
+        return Self {
+            window_id: window_id,
+            gc_id: gc_id,
+            visible: false,
+            text: String::new(),
+        };
+    }
+
+    pub fn update(&mut self, conn: &XConn, xwindow: &XWindow) {
+        // Can't draw without a graphics context
+        let gc_id = match self.gc_id {
+            Some(gc_id) => gc_id,
+            None => return,
+        };
+
+        // Map if not already shown
+        if !self.visible {
+            conn.map_window(self.window_id);
+            self.visible = true;
+        }
+
+        // Position in the top-left of the window, ontop of it
+        conn.configure_window(self.window_id, &helper::values_configure_move_stack_above(xwindow.x as u32, xwindow.y as u32));
+
+        // Draw the geometry text
+        self.text = format!("{},{} {}x{}", xwindow.x, xwindow.y, xwindow.width, xwindow.height);
+        conn.draw_text(self.window_id, gc_id, 4, 13, &self.text);
+    }
+
+    pub fn expose(&self, conn: &XConn, window_id: XWindowID) {
+        // Redraw the last text if it's our window that was uncovered
+        if let Some(gc_id) = self.gc_id {
+            if self.visible && window_id == self.window_id {
+                conn.draw_text(self.window_id, gc_id, 4, 13, &self.text);
+            }
+        }
+    }
+
+    pub fn hide(&mut self, conn: &XConn) {
+        // Unmap if shown
+        if self.visible {
+            conn.unmap_window(self.window_id);
+            self.visible = false;
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use crate::config::RULES;
+
+// Actions applied to newly mapped windows matching a rule
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rule {
+    // Hold the window as the named scratchpad
+    Scratchpad(&'static str),
+
+    // Show the window on every workspace
+    Sticky,
+}
+
+// Get the rules matching a window's WM_CLASS instance or class name
+pub fn matching(instance: &str, class: &str) -> Vec<Rule> {
+    return RULES.iter()
+        .filter(|(name, _)| *name == instance || *name == class)
+        .map(|(_, rule)| *rule)
+        .collect();
+}
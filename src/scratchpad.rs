@@ -0,0 +1,233 @@
+use crate::config::SCRATCHPADS;
+use crate::desktop::Desktop;
+use crate::screen::Screen;
+use crate::windows::Window;
+use crate::x::{XBackend, XWindowID};
+
+use xcb_util::icccm;
+
+struct Scratchpad {
+    // Name used by rules + keybinds
+    name: &'static str,
+
+    // Command spawning this scratchpad (may be empty)
+    command: &'static [&'static str],
+
+    // Window id if this scratchpad has a window
+    window_id: Option<XWindowID>,
+
+    // The Window while hidden, held outside of any workspace
+    hidden: Option<Window>,
+
+    // Command was spawned, but its window hasn't been adopted yet
+    spawning: bool,
+}
+
+impl Scratchpad {
+    fn hide(&mut self, conn: &dyn XBackend, window: Window) {
+        // Window is unmapped now, but still managed
+        conn.set_icccm_wm_state(window.xwindow.id, icccm::WM_STATE_ICONIC);
+        self.hidden = Some(window);
+    }
+}
+
+pub struct Scratchpads(Vec<Scratchpad>);
+
+impl Default for Scratchpads {
+    fn default() -> Self {
+        Self(SCRATCHPADS.iter().map(|(name, command)| Scratchpad {
+            name: name,
+            command: command,
+            window_id: None,
+            hidden: None,
+            spawning: false,
+        }).collect())
+    }
+}
+
+impl Scratchpads {
+    fn get_mut(&mut self, name: &str) -> Option<&mut Scratchpad> {
+        let pad = self.0.iter_mut().find(|pad| pad.name == name);
+        if pad.is_none() {
+            warn!("No such scratchpad: {}", name);
+        }
+        return pad;
+    }
+
+    pub fn name_of(&self, window_id: XWindowID) -> Option<&'static str> {
+        return self.0.iter().find(|pad| pad.window_id == Some(window_id)).map(|pad| pad.name);
+    }
+
+    pub fn hidden_mut(&mut self, window_id: XWindowID) -> Option<&mut Window> {
+        return self.0.iter_mut().filter_map(|pad| pad.hidden.as_mut()).find(|w| w.xwindow.id == window_id);
+    }
+
+    pub fn hidden(&self) -> impl Iterator<Item = &Window> {
+        return self.0.iter().filter_map(|pad| pad.hidden.as_ref());
+    }
+
+    pub fn adopt(&mut self, desktop: &mut Desktop, conn: &dyn XBackend, screen: &Screen, name: &str, mut window: Window) -> Result<(), Window> {
+        // Get the named scratchpad, only adopting if it's empty
+        let pad = match self.get_mut(name) {
+            Some(pad) if pad.window_id.is_none() => pad,
+            _ => return Err(window),
+        };
+        debug!("Adopting window {} as scratchpad: {}", window.xwindow.id, name);
+        pad.window_id = Some(window.xwindow.id);
+        pad.spawning = false;
+
+        // Newly adopted windows were just spawned (or asked for), so show them
+        window.do_centre(conn, screen);
+        desktop.current_mut().window_add(conn, screen, window);
+        return Ok(());
+    }
+
+    pub fn send(&mut self, desktop: &mut Desktop, conn: &dyn XBackend, screen: &Screen, name: &str) {
+        // Only send to an empty scratchpad
+        let pad = match self.get_mut(name) {
+            Some(pad) if pad.window_id.is_none() => pad,
+            Some(_) => {
+                warn!("Scratchpad {} already has a window", name);
+                return;
+            },
+            None => return,
+        };
+
+        // Remove focused window from the workspace, and hold it here
+        if let Some(window) = desktop.current_mut().window_del_focused(conn, screen) {
+            debug!("Sending window {} to scratchpad: {}", window.xwindow.id, name);
+            pad.window_id = Some(window.xwindow.id);
+            pad.hide(conn, window);
+        }
+    }
+
+    pub fn toggle(&mut self, desktop: &mut Desktop, conn: &dyn XBackend, screen: &Screen, name: &str) -> Option<&'static [&'static str]> {
+        let pad = self.get_mut(name)?;
+        debug!("Toggling scratchpad: {}", name);
+
+        // If hidden, show centred on the current workspace
+        if let Some(mut window) = pad.hidden.take() {
+            window.do_centre(conn, screen);
+            desktop.current_mut().window_add(conn, screen, window);
+            return None;
+        }
+
+        if let Some(window_id) = pad.window_id {
+            // If shown on current workspace, hide it
+            if let Some(idx) = desktop.current().windows.index_of(window_id) {
+                let window = desktop.current_mut().window_del(conn, screen, idx, window_id);
+                pad.hide(conn, window);
+                return None;
+            }
+
+            // If left on another workspace, bring it here
+            if let Some((ws, idx)) = desktop.contains_mut(window_id) {
+                let mut window = ws.window_del(conn, screen, idx, window_id);
+                window.do_centre(conn, screen);
+                desktop.current_mut().window_add(conn, screen, window);
+                return None;
+            }
+
+            // Otherwise we lost track of it
+            pad.window_id = None;
+        }
+
+        // Already spawned, wait for its window rather than starting another
+        if pad.spawning {
+            debug!("Scratchpad {} still spawning", name);
+            return None;
+        }
+
+        // Not running, return command to spawn (if any)
+        if pad.command.is_empty() {
+            return None;
+        }
+        pad.spawning = true;
+        return Some(pad.command);
+    }
+
+    pub fn forget(&mut self, window_id: XWindowID) {
+        // Window has gone, empty its scratchpad
+        if let Some(pad) = self.0.iter_mut().find(|pad| pad.window_id == Some(window_id)) {
+            debug!("Scratchpad {} window gone", pad.name);
+            pad.window_id = None;
+            pad.hidden = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scratchpads;
+    use crate::desktop::Desktop;
+    use crate::helper;
+    use crate::mock::{self, RecordingBackend, Request};
+
+    #[test]
+    fn toggle_hides_and_shows_centred_on_current_workspace() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut desktop = Desktop::default();
+        let mut pads = Scratchpads::default();
+
+        pads.adopt(&mut desktop, &conn, &screen, "term", mock::window(2, 0, 0, 400, 300)).ok().unwrap();
+        assert!(desktop.current().windows.contains(2).is_some());
+
+        // Hide, window is held outside of the workspaces
+        pads.toggle(&mut desktop, &conn, &screen, "term");
+        assert!(desktop.contains(2).is_none());
+        assert_eq!(pads.hidden().count(), 1);
+
+        // Show again on another workspace
+        desktop.goto(&conn, &screen, 3);
+        conn.take();
+        assert_eq!(pads.toggle(&mut desktop, &conn, &screen, "term"), None);
+        assert!(desktop.current().windows.contains(2).is_some());
+        assert_eq!(conn.take_visible()[..2], [Request::Configure(2, helper::values_configure_move(760, 390).to_vec()), Request::Map(2)]);
+    }
+
+    #[test]
+    fn toggle_returns_command_when_not_running() {
+        let conn = RecordingBackend::default();
+        let mut desktop = Desktop::default();
+        let mut pads = Scratchpads::default();
+
+        assert!(pads.toggle(&mut desktop, &conn, &mock::screen(), "term").is_some());
+        assert!(pads.toggle(&mut desktop, &conn, &mock::screen(), "scratch").is_none());
+    }
+
+    #[test]
+    fn toggle_spawns_once_until_adopted() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut desktop = Desktop::default();
+        let mut pads = Scratchpads::default();
+
+        // Pressed again before the window maps
+        assert!(pads.toggle(&mut desktop, &conn, &screen, "term").is_some());
+        assert!(pads.toggle(&mut desktop, &conn, &screen, "term").is_none());
+
+        // Spawnable again once the adopted window is gone
+        pads.adopt(&mut desktop, &conn, &screen, "term", mock::window(2, 0, 0, 400, 300)).ok().unwrap();
+        desktop.current_mut().window_del(&conn, &screen, 0, 2);
+        pads.forget(2);
+        assert!(pads.toggle(&mut desktop, &conn, &screen, "term").is_some());
+    }
+
+    #[test]
+    fn send_takes_focused_window_and_forget_clears() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut desktop = Desktop::default();
+        let mut pads = Scratchpads::default();
+        desktop.current_mut().window_add(&conn, &screen, mock::window(2, 0, 0, 400, 300));
+
+        pads.send(&mut desktop, &conn, &screen, "scratch");
+        assert!(desktop.contains(2).is_none());
+        assert_eq!(pads.name_of(2), Some("scratch"));
+
+        pads.forget(2);
+        assert_eq!(pads.name_of(2), None);
+        assert_eq!(pads.hidden().count(), 0);
+    }
+}
@@ -1,8 +1,9 @@
 use crate::config::{WIN_WIDTH_MIN, WIN_HEIGHT_MIN};
 use crate::helper;
 use crate::screen::Screen;
-use crate::x::{XConn, XWindow, XWindowID};
+use crate::x::{CursorIndex, XBackend, XWindow, XWindowID};
 
+use std::cmp;
 use std::collections::{VecDeque, HashSet};
 
 const MIN_SCREEN_ONSCREEN: i32 = 10;
@@ -15,10 +16,130 @@ fn ensure_in_bounds(val: &mut i32, min: i32, max: i32) {
     }
 }
 
+// Snap value to the nearest of targets, if within distance
+fn snap_to(val: i32, targets: &[i32], distance: i32) -> Option<(i32, i32)> {
+    let mut nearest: Option<(i32, i32)> = None;
+    for target in targets {
+        let d = (target - val).abs();
+        if d <= distance && nearest.map_or(true, |(_, nd)| d < nd) {
+            nearest = Some((*target, d));
+        }
+    }
+    return nearest;
+}
+
+// Snap a window being moved to (x, y) against screen and other window edges
+pub fn snap_position(window: &XWindow, x: i32, y: i32, screen: &XWindow, others: &[XWindow], distance: i32) -> (i32, i32) {
+    // Screen edges are always snap targets
+    let mut xs = vec![screen.x, screen.x + screen.width];
+    let mut ys = vec![screen.y, screen.y + screen.height];
+
+    // Other window edges are snap targets where the windows overlap on the other axis
+    for other in others {
+        if y < other.y + other.height && other.y < y + window.height {
+            xs.push(other.x);
+            xs.push(other.x + other.width);
+        }
+        if x < other.x + other.width && other.x < x + window.width {
+            ys.push(other.y);
+            ys.push(other.y + other.height);
+        }
+    }
+
+    // Snap either the leading or trailing edge, whichever is closer
+    let snap_axis = |pos: i32, size: i32, targets: &[i32]| -> i32 {
+        let leading = snap_to(pos, targets, distance);
+        let trailing = snap_to(pos + size, targets, distance);
+        match (leading, trailing) {
+            (Some((t, d)), Some((_, td))) if d <= td => t,
+            (_, Some((t, _))) => t - size,
+            (Some((t, _)), None) => t,
+            (None, None) => pos,
+        }
+    };
+
+    return (snap_axis(x, window.width, &xs), snap_axis(y, window.height, &ys));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+// Corner or edge of a window being resized from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Right,
+    BottomRight,
+    Bottom,
+    BottomLeft,
+    Left,
+}
+
+impl Anchor {
+    pub fn from_position(window: &XWindow, x: i32, y: i32) -> Self {
+        // Split the window into thirds on each axis, and find which the position is in
+        let column = ((x - window.x) * 3 / window.width.max(1)).max(0).min(2);
+        let row = ((y - window.y) * 3 / window.height.max(1)).max(0).min(2);
+
+        match (column, row) {
+            (0, 0) => return Anchor::TopLeft,
+            (1, 0) => return Anchor::Top,
+            (2, 0) => return Anchor::TopRight,
+            (2, 1) => return Anchor::Right,
+            (1, 2) => return Anchor::Bottom,
+            (0, 2) => return Anchor::BottomLeft,
+            (0, 1) => return Anchor::Left,
+
+            // Bottom-right third, or the centre (default to previous behaviour)
+            _ => return Anchor::BottomRight,
+        }
+    }
+
+    // Which horizontal / vertical edges move: -1 for left/top, 1 for right/bottom, 0 for none
+    fn edges(&self) -> (i32, i32) {
+        match self {
+            Anchor::TopLeft     => return (-1, -1),
+            Anchor::Top         => return ( 0, -1),
+            Anchor::TopRight    => return ( 1, -1),
+            Anchor::Right       => return ( 1,  0),
+            Anchor::BottomRight => return ( 1,  1),
+            Anchor::Bottom      => return ( 0,  1),
+            Anchor::BottomLeft  => return (-1,  1),
+            Anchor::Left        => return (-1,  0),
+        }
+    }
+
+    pub fn cursor(&self) -> CursorIndex {
+        match self {
+            Anchor::TopLeft     => return CursorIndex::TopLeftCorner,
+            Anchor::Top         => return CursorIndex::TopSide,
+            Anchor::TopRight    => return CursorIndex::TopRightCorner,
+            Anchor::Right       => return CursorIndex::RightSide,
+            Anchor::BottomRight => return CursorIndex::BottomRightCorner,
+            Anchor::Bottom      => return CursorIndex::BottomSide,
+            Anchor::BottomLeft  => return CursorIndex::BottomLeftCorner,
+            Anchor::Left        => return CursorIndex::LeftSide,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Window {
     pub xwindow: XWindow,
     protocols: HashSet<xcb::Atom>,
+
+    // Shown on every workspace, following the current one
+    pub sticky: bool,
+
+    // Unmaps we requested whose UnmapNotify is yet to arrive. Any other is the client withdrawing
+    pub expected_unmaps: u32,
 }
 
 impl PartialEq for Window {
@@ -32,25 +153,67 @@ impl From<XWindowID> for Window {
         Self {
             xwindow: XWindow::from(window_id),
             protocols: HashSet::new(),
+            sticky: false,
+            expected_unmaps: 0,
         }
     }
 }
 
 impl Window {
-    pub fn do_resize(&mut self, conn: &XConn, screen: &Screen, dx: i32, dy: i32) {
+    pub fn do_resize(&mut self, conn: &dyn XBackend, screen: &Screen, dx: i32, dy: i32) {
         // Iterate current size values
         self.xwindow.width += dx;
         self.xwindow.height += dy;
 
         // Ensure the window sizes are within set bounds
-        ensure_in_bounds(&mut self.xwindow.width,  WIN_WIDTH_MIN  as i32, screen.xwindow.x + screen.xwindow.width  - self.xwindow.x);
-        ensure_in_bounds(&mut self.xwindow.height, WIN_HEIGHT_MIN as i32, screen.xwindow.y + screen.xwindow.height - self.xwindow.y);
+        ensure_in_bounds(&mut self.xwindow.width,  WIN_WIDTH_MIN  as i32, cmp::max(screen.xwindow.x + screen.xwindow.width  - self.xwindow.x, WIN_WIDTH_MIN  as i32));
+        ensure_in_bounds(&mut self.xwindow.height, WIN_HEIGHT_MIN as i32, cmp::max(screen.xwindow.y + screen.xwindow.height - self.xwindow.y, WIN_HEIGHT_MIN as i32));
 
         // Send new window configuration to X
         conn.configure_window(self.xwindow.id, &helper::values_configure_resize(self.xwindow.width as u32, self.xwindow.height as u32));
     }
 
-    pub fn do_move(&mut self, conn: &XConn, screen: &Screen, dx: i32, dy: i32) {
+    pub fn do_resize_from(&mut self, conn: &dyn XBackend, screen: &Screen, anchor: Anchor, dx: i32, dy: i32) {
+        let (horizontal, vertical) = anchor.edges();
+
+        // If only right / bottom edges move, this is a regular resize
+        if horizontal >= 0 && vertical >= 0 {
+            self.do_resize(conn, screen, dx * horizontal, dy * vertical);
+            return;
+        }
+
+        // Get current right / bottom edge positions
+        let right = self.xwindow.x + self.xwindow.width;
+        let bottom = self.xwindow.y + self.xwindow.height;
+
+        // Move left edge keeping right fixed, or move right edge
+        if horizontal < 0 {
+            let mut x = self.xwindow.x + dx;
+            // Never shrink below the minimum, even when the window hangs off-screen
+            ensure_in_bounds(&mut x, cmp::min(screen.xwindow.x, right - WIN_WIDTH_MIN as i32), right - WIN_WIDTH_MIN as i32);
+            self.xwindow.x = x;
+            self.xwindow.width = right - x;
+        } else if horizontal > 0 {
+            self.xwindow.width += dx;
+            ensure_in_bounds(&mut self.xwindow.width, WIN_WIDTH_MIN as i32, cmp::max(screen.xwindow.x + screen.xwindow.width - self.xwindow.x, WIN_WIDTH_MIN as i32));
+        }
+
+        // Move top edge keeping bottom fixed, or move bottom edge
+        if vertical < 0 {
+            let mut y = self.xwindow.y + dy;
+            ensure_in_bounds(&mut y, cmp::min(screen.xwindow.y, bottom - WIN_HEIGHT_MIN as i32), bottom - WIN_HEIGHT_MIN as i32);
+            self.xwindow.y = y;
+            self.xwindow.height = bottom - y;
+        } else if vertical > 0 {
+            self.xwindow.height += dy;
+            ensure_in_bounds(&mut self.xwindow.height, WIN_HEIGHT_MIN as i32, cmp::max(screen.xwindow.y + screen.xwindow.height - self.xwindow.y, WIN_HEIGHT_MIN as i32));
+        }
+
+        // Send new window configuration to X
+        conn.configure_window(self.xwindow.id, &helper::values_configure_geometry(self.xwindow.x as u32, self.xwindow.y as u32, self.xwindow.width as u32, self.xwindow.height as u32));
+    }
+
+    pub fn do_move(&mut self, conn: &dyn XBackend, screen: &Screen, dx: i32, dy: i32) {
         // Iterate current position values
         self.xwindow.x += dx;
         self.xwindow.y += dy;
@@ -63,12 +226,64 @@ impl Window {
         conn.configure_window(self.xwindow.id, &helper::values_configure_move(self.xwindow.x as u32, self.xwindow.y as u32));
     }
 
-    pub fn set_supported_protocols(&mut self, conn: &XConn) {
+    pub fn do_snap(&mut self, conn: &dyn XBackend, screen: &Screen, direction: Direction) {
+        // Calculate distance to the screen edge in direction
+        let (dx, dy) = match direction {
+            Direction::Left  => (screen.xwindow.x - self.xwindow.x, 0),
+            Direction::Right => (screen.xwindow.x + screen.xwindow.width - (self.xwindow.x + self.xwindow.width), 0),
+            Direction::Up    => (0, screen.xwindow.y - self.xwindow.y),
+            Direction::Down  => (0, screen.xwindow.y + screen.xwindow.height - (self.xwindow.y + self.xwindow.height)),
+        };
+
+        // Move there, keeping regular bounds checks
+        self.do_move(conn, screen, dx, dy);
+    }
+
+    pub fn do_centre(&mut self, conn: &dyn XBackend, screen: &Screen) {
+        // Calculate distance from current position to centred position
+        let dx = screen.xwindow.x + (screen.xwindow.width  - self.xwindow.width)  / 2 - self.xwindow.x;
+        let dy = screen.xwindow.y + (screen.xwindow.height - self.xwindow.height) / 2 - self.xwindow.y;
+
+        // Move there, keeping regular bounds checks
+        self.do_move(conn, screen, dx, dy);
+    }
+
+    pub fn do_tile_half(&mut self, conn: &dyn XBackend, screen: &Screen, direction: Direction) {
+        let screen = &screen.xwindow;
+
+        // Calculate geometry for half of screen in direction
+        let (x, y, width, height) = match direction {
+            Direction::Left  => (screen.x, screen.y, screen.width / 2, screen.height),
+            Direction::Right => (screen.x + screen.width / 2, screen.y, screen.width - screen.width / 2, screen.height),
+            Direction::Up    => (screen.x, screen.y, screen.width, screen.height / 2),
+            Direction::Down  => (screen.x, screen.y + screen.height / 2, screen.width, screen.height - screen.height / 2),
+        };
+
+        self.set_geometry(conn, x, y, width, height);
+    }
+
+    pub fn do_maximise(&mut self, conn: &dyn XBackend, screen: &Screen) {
+        let screen = &screen.xwindow;
+        self.set_geometry(conn, screen.x, screen.y, screen.width, screen.height);
+    }
+
+    fn set_geometry(&mut self, conn: &dyn XBackend, x: i32, y: i32, width: i32, height: i32) {
+        // Set new geometry values
+        self.xwindow.x = x;
+        self.xwindow.y = y;
+        self.xwindow.width = width;
+        self.xwindow.height = height;
+
+        // Send new window configuration to X
+        conn.configure_window(self.xwindow.id, &helper::values_configure_geometry(x as u32, y as u32, width as u32, height as u32));
+    }
+
+    pub fn set_supported_protocols(&mut self, conn: &dyn XBackend) {
         // Attempt to get wm protocols for window, and add to our
         // hashset of supported atoms
-        if let Some(protocols) = conn.get_wm_protocols(self.xwindow.id) {
+        if let Ok(protocols) = conn.get_wm_protocols(self.xwindow.id) {
             for protocol in protocols {
-                debug!("{}", conn._get_atom_name(protocol));
+                debug!("Window {} supports protocol: {}", self.xwindow.id, protocol);
                 self.protocols.insert(protocol);
             }
         }
@@ -80,25 +295,73 @@ impl Window {
 }
 
 #[derive(Default)]
-pub struct Windows(VecDeque<Window>);
+pub struct Windows {
+    // Most recently used first, the front window being focused
+    windows: VecDeque<Window>,
+
+    // Focus dropped to the root window, so the front window isn't focused
+    unfocused: bool,
+}
 
 impl Windows {
     pub fn len(&self) -> usize {
-        return self.0.len();
+        return self.windows.len();
     }
 
     pub fn is_empty(&self) -> bool {
-        return self.0.len() == 0;
+        return self.windows.len() == 0;
     }
 
     pub fn move_front(&mut self, idx: usize) {
-        // Only swap with front if window isn't already there
-        if idx != 0 { self.0.swap(0, idx); }
+        // Front window is being focused
+        self.unfocused = false;
+
+        // Only move to front if window isn't already there. We remove + push to keep
+        // the rest of the deque in most-recently-used order
+        if idx != 0 {
+            if let Some(window) = self.windows.remove(idx) {
+                self.windows.push_front(window);
+            }
+        }
+    }
+
+    pub fn nearest_in_direction(&self, idx: usize, direction: Direction) -> Option<usize> {
+        // Get the centre of window we're searching from
+        let (cx, cy) = self.windows.get(idx)?.xwindow.centre();
+
+        let mut nearest: Option<(usize, i64)> = None;
+        for (other_idx, other) in self.windows.iter().enumerate() {
+            if other_idx == idx {
+                continue;
+            }
+            let (ox, oy) = other.xwindow.centre();
+
+            // Distance along the direction and perpendicular to it
+            let (along, across) = match direction {
+                Direction::Left  => (cx - ox, oy - cy),
+                Direction::Right => (ox - cx, oy - cy),
+                Direction::Up    => (cy - oy, ox - cx),
+                Direction::Down  => (oy - cy, ox - cx),
+            };
+
+            // Ignore windows not in this direction
+            if along <= 0 {
+                continue;
+            }
+
+            // Prefer windows in line with this one by weighting perpendicular distance
+            let distance = (along as i64).pow(2) + 4 * (across as i64).pow(2);
+            if nearest.map_or(true, |(_, d)| distance < d) {
+                nearest = Some((other_idx, distance));
+            }
+        }
+
+        return nearest.map(|(other_idx, _)| other_idx);
     }
 
     pub fn index_of(&self, window_id: XWindowID) -> Option<usize> {
         let mut idx: usize = 0;
-        for window in self.0.iter() {
+        for window in self.windows.iter() {
             if window.xwindow.id == window_id {
                 return Some(idx);
             }
@@ -108,32 +371,48 @@ impl Windows {
     }
 
     pub fn add(&mut self, window: Window) {
-        self.0.push_front(window);
+        self.windows.push_front(window);
+        self.unfocused = false;
+    }
+
+    pub fn add_back(&mut self, window: Window) {
+        self.windows.push_back(window);
     }
 
     pub fn remove(&mut self, idx: usize) {
-        self.0.remove(idx);
+        self.windows.remove(idx);
+    }
+
+    // Remove and return all sticky windows, keeping their order
+    pub fn take_sticky(&mut self) -> Vec<Window> {
+        let sticky = self.windows.iter().filter(|w| w.sticky).cloned().collect();
+        self.windows.retain(|w| !w.sticky);
+        return sticky;
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Window> {
-        return self.0.iter();
+        return self.windows.iter();
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Window> {
+        return self.windows.iter_mut();
     }
 
     pub fn iter_rev(&self) -> impl Iterator<Item = &Window> {
-        return self.0.iter().rev();
+        return self.windows.iter().rev();
     }
 
     pub fn get(&self, idx: usize) -> Option<&Window> {
-        return self.0.get(idx);
+        return self.windows.get(idx);
     }
 
     pub fn get_mut(&mut self, idx: usize) -> Option<&mut Window> {
-        return self.0.get_mut(idx);
+        return self.windows.get_mut(idx);
     }
 
     pub fn contains(&self, window_id: XWindowID) -> Option<usize> {
         let mut idx: usize = 0;
-        for window in self.0.iter() {
+        for window in self.windows.iter() {
             if window.xwindow.id == window_id {
                 return Some(idx);
             }
@@ -150,10 +429,206 @@ impl Windows {
     }
 
     pub fn focused(&self) -> Option<&Window> {
-        return self.0.get(0);
+        if self.unfocused {
+            return None;
+        }
+        return self.windows.get(0);
     }
 
     pub fn focused_mut(&mut self) -> Option<&mut Window> {
-        return self.0.get_mut(0);
+        if self.unfocused {
+            return None;
+        }
+        return self.windows.get_mut(0);
+    }
+
+    pub fn unfocus(&mut self) {
+        // Keeps the order, until something is focused again
+        self.unfocused = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{snap_position, Anchor, Direction, Windows, MIN_SCREEN_ONSCREEN};
+    use crate::config::{WIN_WIDTH_MIN, WIN_HEIGHT_MIN};
+    use crate::helper;
+    use crate::mock::{self, RecordingBackend, Request};
+
+    #[test]
+    fn update_geometry_reads_from_backend() {
+        let conn = RecordingBackend::default().with_geometry(2, (10, 20, 300, 200));
+        let mut window = mock::window(2, 0, 0, 0, 0);
+
+        window.xwindow.update_geometry(&conn);
+
+        assert_eq!((window.xwindow.x, window.xwindow.y, window.xwindow.width, window.xwindow.height), (10, 20, 300, 200));
+    }
+
+    #[test]
+    fn do_move_configures_new_position() {
+        let conn = RecordingBackend::default();
+        let mut window = mock::window(2, 100, 100, 400, 300);
+
+        window.do_move(&conn, &mock::screen(), 50, -20);
+
+        assert_eq!((window.xwindow.x, window.xwindow.y), (150, 80));
+        assert_eq!(conn.take(), vec![Request::Configure(2, helper::values_configure_move(150, 80).to_vec())]);
+    }
+
+    #[test]
+    fn do_move_keeps_window_on_screen() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut window = mock::window(2, 100, 100, 400, 300);
+
+        window.do_move(&conn, &screen, -5000, 5000);
+
+        assert_eq!(window.xwindow.x, MIN_SCREEN_ONSCREEN - 400);
+        assert_eq!(window.xwindow.y, screen.xwindow.height - MIN_SCREEN_ONSCREEN);
+    }
+
+    #[test]
+    fn do_snap_moves_to_screen_edges() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut window = mock::window(2, 100, 100, 400, 300);
+
+        window.do_snap(&conn, &screen, Direction::Right);
+        assert_eq!((window.xwindow.x, window.xwindow.y), (screen.xwindow.width - 400, 100));
+
+        window.do_snap(&conn, &screen, Direction::Down);
+        assert_eq!((window.xwindow.x, window.xwindow.y), (screen.xwindow.width - 400, screen.xwindow.height - 300));
+
+        window.do_snap(&conn, &screen, Direction::Left);
+        window.do_snap(&conn, &screen, Direction::Up);
+        assert_eq!((window.xwindow.x, window.xwindow.y), (0, 0));
+    }
+
+    #[test]
+    fn do_centre_centres_on_screen() {
+        let conn = RecordingBackend::default();
+        let mut window = mock::window(2, 0, 0, 400, 300);
+
+        window.do_centre(&conn, &mock::screen());
+
+        assert_eq!((window.xwindow.x, window.xwindow.y), (760, 390));
+        assert_eq!(conn.take(), vec![Request::Configure(2, helper::values_configure_move(760, 390).to_vec())]);
+    }
+
+    #[test]
+    fn nearest_in_direction_prefers_aligned_windows() {
+        let mut windows = Windows::default();
+        windows.add(mock::window(4, 1200, 800, 200, 200));
+        windows.add(mock::window(3, 1000, 0, 200, 200));
+        windows.add(mock::window(2, 0, 600, 200, 200));
+        windows.add(mock::window(1, 0, 0, 200, 200));
+
+        assert_eq!(windows.nearest_in_direction(0, Direction::Right), Some(2));
+        assert_eq!(windows.nearest_in_direction(0, Direction::Down), Some(1));
+        assert_eq!(windows.nearest_in_direction(0, Direction::Left), None);
+        assert_eq!(windows.nearest_in_direction(0, Direction::Up), None);
+        assert_eq!(windows.nearest_in_direction(2, Direction::Left), Some(0));
+    }
+
+    #[test]
+    fn snap_position_snaps_to_screen_edges() {
+        let screen = mock::screen();
+        let window = mock::window(2, 0, 0, 400, 300);
+
+        assert_eq!(snap_position(&window.xwindow, 10, 500, &screen.xwindow, &[], 15), (0, 500));
+        assert_eq!(snap_position(&window.xwindow, 1510, 775, &screen.xwindow, &[], 15), (1520, 780));
+        assert_eq!(snap_position(&window.xwindow, 100, 100, &screen.xwindow, &[], 15), (100, 100));
+    }
+
+    #[test]
+    fn snap_position_snaps_to_adjacent_window_edges() {
+        let screen = mock::screen();
+        let window = mock::window(2, 0, 0, 400, 300);
+        let other = mock::window(3, 800, 200, 200, 200);
+
+        // Right edge of window snaps against left edge of other
+        assert_eq!(snap_position(&window.xwindow, 390, 250, &screen.xwindow, &[other.xwindow.clone()], 15), (400, 250));
+
+        // No snapping to windows we don't overlap vertically
+        assert_eq!(snap_position(&window.xwindow, 390, 700, &screen.xwindow, &[other.xwindow.clone()], 15), (390, 700));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn do_tile_half_and_maximise() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut window = mock::window(2, 100, 100, 400, 300);
+
+        window.do_tile_half(&conn, &screen, Direction::Right);
+        assert_eq!((window.xwindow.x, window.xwindow.y, window.xwindow.width, window.xwindow.height), (960, 0, 960, 1080));
+
+        window.do_maximise(&conn, &screen);
+        assert_eq!(conn.take().pop(), Some(Request::Configure(2, helper::values_configure_geometry(0, 0, 1920, 1080).to_vec())));
+    }
+
+    #[test]
+    fn anchor_from_position_picks_nearest_corner_or_edge() {
+        let window = mock::window(2, 100, 100, 300, 300);
+
+        assert_eq!(Anchor::from_position(&window.xwindow, 110, 110), Anchor::TopLeft);
+        assert_eq!(Anchor::from_position(&window.xwindow, 250, 110), Anchor::Top);
+        assert_eq!(Anchor::from_position(&window.xwindow, 390, 250), Anchor::Right);
+        assert_eq!(Anchor::from_position(&window.xwindow, 110, 390), Anchor::BottomLeft);
+        assert_eq!(Anchor::from_position(&window.xwindow, 250, 250), Anchor::BottomRight);
+    }
+
+    #[test]
+    fn do_resize_from_top_left_keeps_bottom_right_fixed() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut window = mock::window(2, 100, 100, 400, 300);
+
+        window.do_resize_from(&conn, &screen, Anchor::TopLeft, -50, 20);
+        assert_eq!((window.xwindow.x, window.xwindow.y, window.xwindow.width, window.xwindow.height), (50, 120, 450, 280));
+
+        // Can't shrink past minimum size, or grow off-screen
+        window.do_resize_from(&conn, &screen, Anchor::TopLeft, 5000, -5000);
+        assert_eq!((window.xwindow.x, window.xwindow.y), (500 - WIN_WIDTH_MIN as i32, 0));
+        assert_eq!((window.xwindow.width, window.xwindow.height), (WIN_WIDTH_MIN as i32, 400));
+    }
+
+    #[test]
+    fn do_resize_from_keeps_minimum_size_off_screen() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+
+        // Mostly off the top-left corner
+        let mut window = mock::window(2, -395, -295, 400, 300);
+        window.do_resize_from(&conn, &screen, Anchor::TopLeft, 10, 10);
+        assert_eq!((window.xwindow.width, window.xwindow.height), (WIN_WIDTH_MIN as i32, WIN_HEIGHT_MIN as i32));
+        assert_eq!((window.xwindow.x, window.xwindow.y), (5 - WIN_WIDTH_MIN as i32, 5 - WIN_HEIGHT_MIN as i32));
+
+        // Mostly off the bottom-right corner
+        let mut window = mock::window(2, 1915, 1075, 400, 300);
+        window.do_resize_from(&conn, &screen, Anchor::BottomRight, -10, -10);
+        assert_eq!((window.xwindow.width, window.xwindow.height), (WIN_WIDTH_MIN as i32, WIN_HEIGHT_MIN as i32));
+    }
+
+    #[test]
+    fn do_resize_from_edge_ignores_other_axis() {
+        let conn = RecordingBackend::default();
+        let mut window = mock::window(2, 100, 100, 400, 300);
+
+        window.do_resize_from(&conn, &mock::screen(), Anchor::Right, 50, 50);
+        assert_eq!((window.xwindow.width, window.xwindow.height), (450, 300));
+    }
+
+    #[test]
+    fn do_resize_clamps_to_minimum_and_screen() {
+        let conn = RecordingBackend::default();
+        let screen = mock::screen();
+        let mut window = mock::window(2, 100, 100, 400, 300);
+
+        window.do_resize(&conn, &screen, -5000, -5000);
+        assert_eq!((window.xwindow.width, window.xwindow.height), (WIN_WIDTH_MIN as i32, WIN_HEIGHT_MIN as i32));
+
+        window.do_resize(&conn, &screen, 5000, 5000);
+        assert_eq!((window.xwindow.width, window.xwindow.height), (screen.xwindow.width - 100, screen.xwindow.height - 100));
+    }
+}
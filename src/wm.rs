@@ -1,20 +1,87 @@
-use crate::config::{KEYBINDS, MODKEY};
+use crate::config::{AUTO_RAISE_DELAY, DRAG_UPDATE_INTERVAL, FOCUS_MODEL, KEYBINDS, MAX_WORKSPACES, MODES, MODKEY, MOUSEBINDS, SHOW_DRAG_GEOMETRY, SNAP_DISTANCE};
 use crate::desktop::Desktop;
 use crate::helper;
+use crate::readout::Readout;
+use crate::rules::{self, Rule};
+use crate::scratchpad::Scratchpads;
 use crate::screen::Screen;
-use crate::windows::Window;
-use crate::x::{CursorIndex, XConn, XWindowID};
+use crate::windows::{self, Anchor, Direction, Window};
+use crate::x::{CursorIndex, XBackend, XConn, XError, XWindow, XWindowID};
 
 use std::process;
-use xcb_util::{cursor, ewmh};
+use std::time::{Duration, Instant};
+use x11::keysym;
+use xcb_util::{cursor, ewmh, icccm, keysyms};
+
+// Key bind of tuple: (mask, key, function)
+pub type Keybind = (xcb::ModMask, xcb::Keysym, fn(&mut WM));
+
+// Mouse bind of tuple: (mask, button, context, action)
+pub type Mousebind = (xcb::ModMask, xcb::ButtonIndex, MouseContext, MouseAction);
+
+// Name announced for the default keybinding mode
+const DEFAULT_MODE: &str = "default";
+
+// How long to wait for a replaced window manager to release the screen
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(PartialEq)]
 enum MouseMode {
     Ground,
-    Resize,
+    Resize(Anchor),
     Move,
 }
 
+#[derive(PartialEq)]
+pub enum MouseContext {
+    // Pointer over the root window
+    Root,
+
+    // Pointer over a client window
+    Client,
+}
+
+pub enum MouseAction {
+    // Drag the window under the pointer
+    Move,
+
+    // Drag-resize the window under the pointer, from its nearest corner / edge
+    Resize,
+
+    // Call function with the window under the pointer (root window for MouseContext::Root)
+    Call(fn(&mut WM, XWindowID)),
+}
+
+// Picked by FOCUS_MODEL in config, so any but the chosen one goes unused
+#[allow(dead_code)]
+pub enum FocusModel {
+    // Focus changes only by clicking (or keyboard)
+    Click,
+
+    // Focus follows the mouse into windows, staying put over the root window
+    Sloppy,
+
+    // Focus follows the mouse, dropping to the root window over it
+    Strict,
+}
+
+// Core cursors to load, and their glyphs
+const CURSORS: &[(CursorIndex, u16)] = &[
+    (CursorIndex::LeftPtr,           cursor::LEFT_PTR),
+    (CursorIndex::TopLeftCorner,     cursor::TOP_LEFT_CORNER),
+    (CursorIndex::TopSide,           cursor::TOP_SIDE),
+    (CursorIndex::TopRightCorner,    cursor::TOP_RIGHT_CORNER),
+    (CursorIndex::RightSide,         cursor::RIGHT_SIDE),
+    (CursorIndex::BottomRightCorner, cursor::BOTTOM_RIGHT_CORNER),
+    (CursorIndex::BottomSide,        cursor::BOTTOM_SIDE),
+    (CursorIndex::BottomLeftCorner,  cursor::BOTTOM_LEFT_CORNER),
+    (CursorIndex::LeftSide,          cursor::LEFT_SIDE),
+    (CursorIndex::Fleur,             cursor::FLEUR),
+];
+
+// _NET_WM_DESKTOP value for windows shown on all desktops
+const ALL_DESKTOPS: u32 = 0xFFFFFFFF;
+
 pub struct WM<'a> {
     // X connection
     pub conn: XConn<'a>,
@@ -23,26 +90,65 @@ pub struct WM<'a> {
     pub desktop: Desktop,
     pub screen:  Screen,
 
+    // Scratchpad windows, held outside of the workspaces while hidden
+    pub scratchpads: Scratchpads,
+
+    // ICCCM window manager selection and the window we own it with
+    wm_selection: xcb::Atom,
+    wm_window: XWindowID,
+
     // Mouse mode from button press events
     mouse_mode: MouseMode,
     last_mouse_x: i32,
     last_mouse_y: i32,
     selected: Option<XWindowID>,
+
+    // Pointer and selected window positions at the start of a move
+    drag_mouse_x: i32,
+    drag_mouse_y: i32,
+    drag_window_x: i32,
+    drag_window_y: i32,
+
+    // Geometry readout shown while dragging
+    readout: Readout,
+
+    // Time of last window update while dragging, and pointer position held back since
+    last_drag_update: Instant,
+    pending_drag: Option<(i32, i32)>,
+
+    // Window focused by the mouse waiting to be raised, and when
+    pending_raise: Option<(XWindowID, Instant)>,
+
+    // Index into MODES of the current keybinding mode, None for default
+    mode: Option<usize>,
+
+    // Window last announced via _NET_ACTIVE_WINDOW
+    active_window: XWindowID,
 }
 
 impl<'a> WM<'a> {
-    pub fn register(conn: &'a ewmh::Connection, screen_idx: i32) -> Self {
+    pub fn register(conn: &'a ewmh::Connection, screen_idx: i32, replace: bool) -> Result<Self, XError> {
         // Create new XConn wrapping xcb::Connection
-        let mut xconn = XConn::new(conn);
+        let mut xconn = XConn::new(conn)?;
 
         // Get root window id for screen index
-        let root_id = xconn.get_setup().roots().nth(screen_idx as usize).expect("Getting root window id for screen index").root();
+        let root_id = match xconn.get_setup().roots().nth(screen_idx as usize) {
+            Some(screen) => screen.root(),
+            None => return Err(XError::InvalidScreen(screen_idx)),
+        };
 
         // Create new screen object
         let mut screen = Screen::new(screen_idx, root_id);
 
-        // Try register the root window for necessary window management events
-        xconn.change_window_attributes_checked(root_id, &helper::values_attributes_root());
+        // Acquire the ICCCM WM_S<screen> selection, replacing any existing owner if requested
+        let (wm_selection, wm_window) = Self::acquire_selection(&mut xconn, screen_idx, root_id, replace)?;
+
+        // Try register the root window for necessary window management events. This only fails if
+        // another window manager (one not following ICCCM selection rules) is running
+        if let Err(err) = xconn.change_window_attributes_checked(root_id, &helper::values_attributes_root()) {
+            debug!("Selecting substructure redirect failed: {}", err);
+            return Err(XError::OtherWMRunning(screen_idx));
+        }
 
         // Set supported atoms
         xconn.set_supported(
@@ -51,20 +157,23 @@ impl<'a> WM<'a> {
                 xconn.atoms.SUPPORTED,
                 xconn.atoms.WM_PROTOCOLS,
                 xconn.atoms.WM_DELETE_WINDOW,
+                xconn.atoms.WM_STATE,
+                xconn.atoms.WM_STATE_STICKY,
+                xconn.atoms.WM_STATE_HIDDEN,
+                xconn.atoms.WM_DESKTOP,
+                xconn.atoms.NUMBER_OF_DESKTOPS,
+                xconn.atoms.CURRENT_DESKTOP,
+                xconn.atoms.DESKTOP_NAMES,
+                xconn.atoms.ACTIVE_WINDOW,
             ]
         );
 
-        // For configured keybinds, register X to grab keys on the root window
-        for (mask, keysym, _) in KEYBINDS {
-            xconn.grab_key(root_id, *mask, *keysym);
-        }
-
-        // Register root window to grab necessary mouse button events
-        xconn.grab_button(root_id, helper::ROOT_BUTTON_GRAB_MASK, xcb::BUTTON_INDEX_1, MODKEY, true);
-        xconn.grab_button(root_id, helper::ROOT_BUTTON_GRAB_MASK, xcb::BUTTON_INDEX_3, MODKEY, true);
-
         // Create necessary core cursors
-        xconn.create_core_cursor(CursorIndex::LeftPtr, cursor::LEFT_PTR);
+        for (index, glyph) in CURSORS {
+            if let Err(err) = xconn.create_core_cursor(*index, *glyph) {
+                warn!("Failed creating cursor: {}", err);
+            }
+        }
 
         // Now set the default starting cursor
         xconn.set_cursor(root_id, CursorIndex::LeftPtr);
@@ -72,26 +181,48 @@ impl<'a> WM<'a> {
         // Perform initial screen geometry fetch
         screen.xwindow.update_geometry(&xconn);
 
+        // Create drag geometry readout window
+        let readout = Readout::new(&xconn, screen_idx, root_id);
+
         // Create new Self
         let mut new = Self {
             conn: xconn,
             desktop: Desktop::default(),
             screen:  screen,
+            scratchpads: Scratchpads::default(),
+            wm_selection: wm_selection,
+            wm_window: wm_window,
             mouse_mode: MouseMode::Ground,
             last_mouse_x: 0,
             last_mouse_y: 0,
             selected: None,
+            drag_mouse_x: 0,
+            drag_mouse_y: 0,
+            drag_window_x: 0,
+            drag_window_y: 0,
+            readout: readout,
+            last_drag_update: Instant::now(),
+            pending_drag: None,
+            pending_raise: None,
+            mode: None,
+            active_window: xcb::NONE,
         };
 
+        // Register X to grab keys for the default mode, and necessary mouse button events, on the root window.
+        // Only one-shot modes can fail as a whole, individual keys are logged
+        new.grab_keys().ok();
+        new.grab_buttons();
+
         // Perform initial client fetch
-        for existing_id in new.conn.query_tree(root_id).iter() {
+        for existing_id in new.conn.query_tree(root_id)?.iter() {
             // Shadow the reference with actual value
             let existing_id = *existing_id;
 
             // Get attributes for id. If not there, window was probably closed since query
-            let attr = new.conn.get_window_attributes(existing_id);
-            if attr.is_none() { continue; }
-            let attr = attr.unwrap();
+            let attr = match new.conn.get_window_attributes(existing_id) {
+                Ok(attr) => attr,
+                Err(_) => continue,
+            };
 
             // Ignore windows in override redirect mode / invisible
             if attr.override_redirect() || attr.map_state() as u32 != xcb::MAP_STATE_VIEWABLE {
@@ -104,18 +235,66 @@ impl<'a> WM<'a> {
         }
 
         // Return new Self :)
-        return new;
+        return Ok(new);
+    }
+
+    fn acquire_selection(conn: &mut XConn, screen_idx: i32, root_id: XWindowID, replace: bool) -> Result<(xcb::Atom, XWindowID), XError> {
+        // Get the manager selection atom for this screen
+        let selection = conn.intern_atom(&format!("WM_S{}", screen_idx))?;
+
+        // Check for an existing window manager owning the selection
+        let owner = conn.get_selection_owner(selection)?;
+        if owner != xcb::NONE {
+            if !replace {
+                return Err(XError::OtherWMRunning(screen_idx));
+            }
+
+            // Track the existing owner so we know when it has exited
+            info!("Replacing existing window manager on screen {}", screen_idx);
+            conn.change_window_attributes(owner, &helper::values_attributes_structure_events());
+        }
+
+        // Create our own selection owner window, and get a timestamp from it
+        let window_id = conn.create_check_window(root_id);
+        let time = conn.get_timestamp(window_id)?;
+
+        // Try take ownership of the selection, confirming we actually got it
+        conn.set_selection_owner(window_id, selection, time);
+        if conn.get_selection_owner(selection)? != window_id {
+            return Err(XError::SelectionFailed(screen_idx));
+        }
+
+        // If we are replacing, wait for the previous owner to let go of the screen
+        if owner != xcb::NONE && !conn.wait_for_destroy(owner, REPLACE_TIMEOUT) {
+            warn!("Previous window manager did not exit in time, continuing anyway");
+        }
+
+        // Announce ourselves as the new selection owner
+        conn.send_manager_message(root_id, time, selection, window_id);
+
+        // Return the selection and owning window
+        return Ok((selection, window_id));
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> Result<(), XError> {
         info!("Started running");
 
         // Perform an initial activation of current workspace in case contains any windows
         self.desktop.current_mut().activate(&self.conn, &self.screen);
+        self.update_desktop_properties();
+        self.update_active_window();
+        self.conn.set_mode(self.screen.xwindow.id, DEFAULT_MODE);
 
         loop {
-            // Get next event
-            let event = self.conn.next_event();
+            // Get next event, waking up in time for any pending drag update / raise
+            let timeout = self.next_timeout();
+            let event = match self.conn.next_event(timeout)? {
+                Some(event) => event,
+                None => {
+                    self.on_timeout();
+                    continue;
+                },
+            };
 
             // Cast (this is unsafe) and pass event to appropriate function.
             //
@@ -125,6 +304,9 @@ impl<'a> WM<'a> {
             // 8th bit value.
             unsafe {
                 match event.response_type() & !0x80 {
+                    // Errors from previously sent requests
+                    0 => self.on_error(xcb::cast_event(&event)),
+
                     // Handle necessary events
                     xcb::CONFIGURE_NOTIFY => self.on_configure_notify(xcb::cast_event(&event)),
                     xcb::CONFIGURE_REQUEST => self.on_configure_request(xcb::cast_event(&event)),
@@ -133,17 +315,40 @@ impl<'a> WM<'a> {
                     xcb::DESTROY_NOTIFY => self.on_destroy_notify(xcb::cast_event(&event)),
                     xcb::ENTER_NOTIFY => self.on_enter_notify(xcb::cast_event(&event)),
                     xcb::MOTION_NOTIFY => self.on_motion_notify(xcb::cast_event(&event)),
+                    xcb::EXPOSE => self.on_expose(xcb::cast_event(&event)),
                     xcb::BUTTON_PRESS => self.on_button_press(xcb::cast_event(&event)),
                     xcb::BUTTON_RELEASE => self.on_button_release(xcb::cast_event(&event)),
                     xcb::KEY_PRESS => self.on_key_press(xcb::cast_event(&event)),
+                    xcb::MAPPING_NOTIFY => self.on_mapping_notify(xcb::cast_event(&event)),
+                    xcb::PROPERTY_NOTIFY => self.on_property_notify(xcb::cast_event(&event)),
                     xcb::CLIENT_MESSAGE => self.on_client_message(xcb::cast_event(&event)),
+                    xcb::SELECTION_CLEAR => self.on_selection_clear(xcb::cast_event(&event)),
 
                     unhandled => debug!("unhandled event type: {}", unhandled),
                 }
             }
+
+            // Focus may have changed by any route (keybinds, mouse, workspace switches...), keep pagers up to date
+            self.update_active_window();
         }
     }
 
+    fn on_error(&mut self, err: &xcb::GenericError) {
+        let err = XError::from_generic(err);
+
+        // Requests for a window that has already gone are expected (we race the client). Evict it
+        if err.is_bad_window() {
+            if let XError::Protocol { resource_id, .. } = err {
+                debug!("on_error: bad window {}, evicting", resource_id);
+                self._unmap_window(resource_id);
+            }
+            return;
+        }
+
+        // Anything else we just log
+        warn!("{}", err);
+    }
+
     fn on_configure_notify(&mut self, event: &xcb::ConfigureNotifyEvent) {
         // We only care about this if it's the route window being configured
         if event.window() == self.screen.xwindow.id {
@@ -203,7 +408,17 @@ impl<'a> WM<'a> {
     }
 
     fn on_map_request(&mut self, event: &xcb::MapRequestEvent) {
-        if self.desktop.contains(event.window()).is_none() {
+        if let Some(name) = self.scratchpads.name_of(event.window()) {
+            // Hidden scratchpad asking to be shown, toggle it back
+            if self.desktop.contains(event.window()).is_none() {
+                debug!("on_map_request for hidden scratchpad: {}", name);
+                self.scratchpads.toggle(&mut self.desktop, &self.conn, &self.screen, name);
+            }
+        } else if self.desktop.hidden_index_of(event.window()).is_some() {
+            // Minimized window asking to be shown
+            debug!("on_map_request for minimized window: {}", event.window());
+            self.window_restore(event.window());
+        } else if self.desktop.contains(event.window()).is_none() {
             debug!("on_map_request: {}", event.window());
 
             // Window not already tracked! Map!
@@ -215,7 +430,7 @@ impl<'a> WM<'a> {
 
     fn _map_window(&mut self, window_id: XWindowID) {
         // Try get window types so we can check if we ignore it
-        if let Some(window_type) = self.conn.get_wm_window_type(window_id) {
+        if let Ok(window_type) = self.conn.get_wm_window_type(window_id) {
             if !(window_type.contains(&self.conn.atoms.WM_WINDOW_TYPE_NORMAL)  ||
                  window_type.contains(&self.conn.atoms.WM_WINDOW_TYPE_DIALOG)  ||
                  window_type.contains(&self.conn.atoms.WM_WINDOW_TYPE_TOOLBAR) ||
@@ -237,13 +452,63 @@ impl<'a> WM<'a> {
         // Get supported protocols
         window.set_supported_protocols(&self.conn);
 
+        // Clients may ask to be sticky before mapping
+        window.sticky = self.conn.get_wm_state(window_id).unwrap_or_default().contains(&self.conn.atoms.WM_STATE_STICKY);
+
+        // Apply any rules matching the window class
+        let (instance, class) = self.conn.get_wm_class(window_id).unwrap_or_default();
+        for rule in rules::matching(&instance, &class) {
+            match rule {
+                Rule::Sticky => window.sticky = true,
+
+                Rule::Scratchpad(name) => {
+                    window = match self.scratchpads.adopt(&mut self.desktop, &self.conn, &self.screen, name, window) {
+                        Ok(()) => return,
+                        Err(window) => window,
+                    };
+                },
+            }
+        }
+
         // Add the Window to the current workspace
-        self.desktop.current_mut().window_add(&self.conn, &self.screen, window);        
+        let sticky = window.sticky;
+        self.desktop.current_mut().window_add(&self.conn, &self.screen, window);
+        if sticky {
+            self.conn.set_wm_state_atom(window_id, self.conn.atoms.WM_STATE_STICKY, true);
+        }
+        self.update_wm_desktop(window_id);
     }
 
     fn on_unmap_notify(&mut self, event: &xcb::UnmapNotifyEvent) {
         debug!("on_unmap_notify: {}", event.window());
-        self._unmap_window(event.window());
+
+        // Ignore those caused by our own unmaps (workspace switches, sends, minimizing...)
+        let synthetic = event.response_type() & 0x80 != 0;
+        if !synthetic && self.expect_unmap(event.window()) {
+            debug!("on_unmap_notify: expected unmap for {}", event.window());
+            return;
+        }
+
+        // The client is withdrawing the window: a real unmap if it was mapped, or a synthetic
+        // one sent to the root if it was already unmapped (iconic). Either way, drop WM_STATE
+        if self._unmap_window(event.window()) {
+            self.conn.delete_icccm_wm_state(event.window());
+        }
+    }
+
+    fn expect_unmap(&mut self, window_id: XWindowID) -> bool {
+        // Find the window wherever it's tracked, counting off one expected unmap
+        let window = match self.desktop.window_mut(window_id) {
+            Some(window) => Some(window),
+            None => self.scratchpads.hidden_mut(window_id),
+        };
+        if let Some(window) = window {
+            if window.expected_unmaps > 0 {
+                window.expected_unmaps -= 1;
+                return true;
+            }
+        }
+        return false;
     }
 
     fn on_destroy_notify(&mut self, event: &xcb::DestroyNotifyEvent) {
@@ -251,14 +516,21 @@ impl<'a> WM<'a> {
         self._unmap_window(event.window());
     }
 
-    fn _unmap_window(&mut self, window_id: XWindowID) {
-        // Unmap / destroy event shouldn't be generated by ourselves (we toggle tracking to ensure this).
-        // We can safely assume that we should just remove whatever Window from whatever workspace it may be in
+    fn _unmap_window(&mut self, window_id: XWindowID) -> bool {
+        // Unmap / destroy event wasn't generated by ourselves (on_unmap_notify filters expected unmaps).
+        // We can safely assume that we should just remove whatever Window from wherever it may be, returning if tracked
         if let Some((ws, idx)) = self.desktop.contains_mut(window_id) {
             ws.window_del(&self.conn, &self.screen, idx, window_id);
+            self.scratchpads.forget(window_id);
+        } else if self.scratchpads.name_of(window_id).is_some() {
+            self.scratchpads.forget(window_id);
+        } else if let Some(ws_idx) = self.desktop.hidden_index_of(window_id) {
+            self.desktop.get_mut(ws_idx).hidden_take(window_id);
         } else {
             debug!("on_unmap/destroy_notify for untracked window: {}", window_id);
+            return false;
         }
+        return true;
     }
 
     fn on_enter_notify(&mut self, event: &xcb::EnterNotifyEvent) {
@@ -268,51 +540,205 @@ impl<'a> WM<'a> {
             return;
         }
 
-        // We should only receive these from child windows we've tracked, so if in current workspace we set input focus
+        // Clicks only in click-to-focus
+        if let FocusModel::Click = FOCUS_MODEL {
+            return;
+        }
+
+        // Pointer moved from a window onto the root. Strict focus follows it there
+        if event.event() == self.screen.xwindow.id {
+            if let FocusModel::Strict = FOCUS_MODEL {
+                if event.detail() as u32 == xcb::NOTIFY_DETAIL_INFERIOR {
+                    debug!("on_enter_notify: root, dropping focus");
+                    self.pending_raise = None;
+                    self.desktop.current_mut().window_unfocus(&self.conn, &self.screen);
+                    self.update_active_window();
+                }
+            }
+            return;
+        }
+
+        // We should only receive these from child windows we've tracked, so if in current workspace we focus
         if self.desktop.current().windows.contains(event.event()).is_some() {
             debug!("on_enter_notify: {}", event.event());
-            self.conn.set_input_focus(event.event());
+
+            // Raise now, later, or never
+            match AUTO_RAISE_DELAY {
+                Some(delay) if delay == Duration::from_secs(0) => {
+                    self.desktop.current_mut().window_focus(&self.conn, &self.screen, event.event());
+                },
+                Some(delay) => {
+                    self.desktop.current_mut().window_focus_no_raise(&self.conn, &self.screen, event.event());
+                    self.pending_raise = Some((event.event(), Instant::now() + delay));
+                },
+                None => {
+                    self.desktop.current_mut().window_focus_no_raise(&self.conn, &self.screen, event.event());
+                },
+            }
         } else {
             debug!("on_enter_notify for window untracked / not in current workspace: {}", event.event());
         }
     }
 
+    fn next_timeout(&self) -> Option<Duration> {
+        // Soonest of the held back drag update and pending raise, if any
+        let drag = self.pending_drag.map(|_| self.last_drag_update + DRAG_UPDATE_INTERVAL);
+        let raise = self.pending_raise.map(|(_, at)| at);
+        return drag.into_iter().chain(raise).min().map(|at| at.saturating_duration_since(Instant::now()));
+    }
+
+    fn on_timeout(&mut self) {
+        let now = Instant::now();
+
+        // Pointer stopped mid-drag, catch the window up with it
+        if let Some((mouse_x, mouse_y)) = self.pending_drag {
+            if now >= self.last_drag_update + DRAG_UPDATE_INTERVAL {
+                self.pending_drag = None;
+                self.last_drag_update = now;
+                self.drag_to(mouse_x, mouse_y);
+            }
+        }
+
+        // Raise delay may not have passed yet if woken for the drag
+        if let Some((_, at)) = self.pending_raise {
+            if now >= at {
+                self.on_raise_timeout();
+            }
+        }
+    }
+
+    fn on_raise_timeout(&mut self) {
+        // Raise the window if the pointer left it focused all this time
+        if let Some((window_id, _)) = self.pending_raise.take() {
+            if self.desktop.current().windows.focused().map(|w| w.xwindow.id) == Some(window_id) {
+                debug!("Auto-raising window: {}", window_id);
+                self.desktop.current_mut().window_focus(&self.conn, &self.screen, window_id);
+            }
+        }
+    }
+
     fn on_motion_notify(&mut self, event: &xcb::MotionNotifyEvent) {
+        // Throttle window updates, holding back the position to apply when the interval is up
+        if self.last_drag_update.elapsed() < DRAG_UPDATE_INTERVAL {
+            self.pending_drag = Some((event.root_x() as i32, event.root_y() as i32));
+            return;
+        }
+        debug!("on_motion_notify");
+        self.pending_drag = None;
+        self.last_drag_update = Instant::now();
+
+        // Drag selected window to pointer position
+        self.drag_to(event.root_x() as i32, event.root_y() as i32);
+    }
+
+    fn on_expose(&mut self, event: &xcb::ExposeEvent) {
+        // Only redraw once the last of a series of exposures arrives
+        if event.count() == 0 {
+            self.readout.expose(&self.conn, event.window());
+        }
+    }
+
+    fn drag_to(&mut self, mouse_x: i32, mouse_y: i32) {
         // Only perform something if there's a window selected
         if let Some(selected) = self.selected {
-            debug!("on_motion_notify");
-
             // Calculate dx, dy
-            let dx = event.root_x() as i32 - self.last_mouse_x;
-            let dy = event.root_y() as i32 - self.last_mouse_y;
+            let dx = mouse_x - self.last_mouse_x;
+            let dy = mouse_y - self.last_mouse_y;
 
             // Set new last mouse positions
-            self.last_mouse_x = event.root_x() as i32;
-            self.last_mouse_y = event.root_y() as i32;
+            self.last_mouse_x = mouse_x;
+            self.last_mouse_y = mouse_y;
 
             // Get the selected Window, this should be focused but may not always
             if let Some(idx) = self.desktop.current().windows.contains(selected) {
+                // Get geometries of the other windows on screen to snap against
+                let others: Vec<XWindow> = self.desktop.current().windows.iter()
+                    .filter(|window| window.xwindow.id != selected)
+                    .map(|window| window.xwindow.clone())
+                    .collect();
+
                 let selected = self.desktop.current_mut().windows.get_mut(idx).unwrap();
 
                 // React depending on current MouseMode
                 match self.mouse_mode {
                     MouseMode::Move => {
-                        selected.do_move(&self.conn, &self.screen, dx, dy);
+                        // Position the window would have following the pointer exactly
+                        let x = self.drag_window_x + self.last_mouse_x - self.drag_mouse_x;
+                        let y = self.drag_window_y + self.last_mouse_y - self.drag_mouse_y;
+
+                        // Snap this to nearby edges, and move there
+                        let (x, y) = windows::snap_position(&selected.xwindow, x, y, &self.screen.xwindow, &others, SNAP_DISTANCE);
+                        selected.do_move(&self.conn, &self.screen, x - selected.xwindow.x, y - selected.xwindow.y);
                     },
 
-                    MouseMode::Resize => {
-                        selected.do_resize(&self.conn, &self.screen, dx, dy);
+                    MouseMode::Resize(anchor) => {
+                        selected.do_resize_from(&self.conn, &self.screen, anchor, dx, dy);
                     },
 
-                    _ => panic!("MouseMode::Ground reached in on_motion_notify()"),
+                    MouseMode::Ground => return,
+                }
+
+                // Show the new window geometry
+                if SHOW_DRAG_GEOMETRY {
+                    self.readout.update(&self.conn, &selected.xwindow);
                 }
             }
         }
     }
 
     fn on_button_press(&mut self, event: &xcb::ButtonPressEvent) {
-        // If button press not in a child window to root, we don't care
-        if event.child() == xcb::WINDOW_NONE {
+        // Plain click grabbed on an unfocused window: focus + raise it, then pass the click on
+        if event.event() != self.screen.xwindow.id {
+            debug!("on_button_press: click to focus {}", event.event());
+            self.desktop.current_mut().window_focus(&self.conn, &self.screen, event.event());
+            self.conn.allow_events_replay_pointer();
+            return;
+        }
+
+        // Press over root window itself, or a child window to it
+        let (context, target) = match event.child() {
+            xcb::WINDOW_NONE => (MouseContext::Root, self.screen.xwindow.id),
+            child => (MouseContext::Client, child),
+        };
+
+        // Try get action for mouse bind
+        let mask = self.conn.clean_mask(event.state());
+        let button = event.detail() as u32;
+        let action = match MOUSEBINDS.iter().find(|(m, b, c, _)| *m == mask && *b == button && *c == context) {
+            Some((_, _, _, action)) => action,
+            None => {
+                debug!("on_button_press: unhandled button {} with mask {}", button, mask);
+                return;
+            },
+        };
+
+        // Get MouseMode for bind action
+        let mouse_mode = match action {
+            MouseAction::Move => {
+                debug!("on_button_press: mouse move {}", target);
+                MouseMode::Move
+            },
+
+            MouseAction::Resize => {
+                debug!("on_button_press: mouse resize {}", target);
+
+                // Resize from the corner / edge nearest to the pointer
+                let anchor = match self.desktop.current().windows.contains(target) {
+                    Some(idx) => Anchor::from_position(&self.desktop.current().windows.get(idx).unwrap().xwindow, event.root_x() as i32, event.root_y() as i32),
+                    None => Anchor::BottomRight,
+                };
+                MouseMode::Resize(anchor)
+            },
+
+            MouseAction::Call(mousefn) => {
+                debug!("on_button_press: mouse call {}", target);
+                mousefn(self, target);
+                return;
+            },
+        };
+
+        // Dragging the root window makes no sense
+        if context == MouseContext::Root {
             return;
         }
 
@@ -323,41 +749,46 @@ impl<'a> WM<'a> {
         self.last_mouse_x = event.root_x() as i32;
         self.last_mouse_y = event.root_y() as i32;
 
-        // Start grabbing pointer
-        self.conn.grab_pointer(self.screen.xwindow.id, helper::ROOT_POINTER_GRAB_MASK);
+        // Set drag start positions
+        self.drag_mouse_x = self.last_mouse_x;
+        self.drag_mouse_y = self.last_mouse_y;
+        if let Some(idx) = self.desktop.current().windows.contains(event.child()) {
+            let window = self.desktop.current().windows.get(idx).unwrap();
+            self.drag_window_x = window.xwindow.x;
+            self.drag_window_y = window.xwindow.y;
+        }
+
+        // Start grabbing pointer, showing the cursor for this mouse mode
+        let cursor = match mouse_mode {
+            MouseMode::Resize(anchor) => anchor.cursor(),
+            _ => CursorIndex::Fleur,
+        };
+        self.conn.grab_pointer(self.screen.xwindow.id, helper::ROOT_POINTER_GRAB_MASK, cursor);
 
         // If window id different to focused, focus it
         if !self.desktop.current().windows.is_focused(event.child()) {
             self.desktop.current_mut().window_focus(&self.conn, &self.screen, event.child());
         }
 
-        // Get MouseButton for event
-        match event.detail() as u32 {
-            // Left click, set mouse mode
-            xcb::BUTTON_INDEX_1 => {
-                debug!("on_button_press: mouse left click");
-                self.mouse_mode = MouseMode::Move;
-            },
-
-            // Right click, set mouse mode
-            xcb::BUTTON_INDEX_3 => {
-                debug!("on_button_press: mouse right click");
-                self.mouse_mode = MouseMode::Resize;
-            },
-
-            _ => panic!("Unhandled button press in on_button_press"),
-        }
+        // Set the mouse mode
+        self.mouse_mode = mouse_mode;
     }
 
     fn on_button_release(&mut self, event: &xcb::ButtonReleaseEvent) {
-        // We only log these in debug builds
-        #[cfg(debug_assertions)]
-        match event.detail() as u32  {
-            xcb::BUTTON_INDEX_1 => debug!("on_button_release: mouse left click"),
-            xcb::BUTTON_INDEX_3 => debug!("on_button_release: mouse right click"),
-            _ => panic!("Unhandled button release in on_button_release"),
+        debug!("on_button_release: button {}", event.detail());
+
+        // Apply final pointer position, in case the last motion was throttled
+        self.pending_drag = None;
+        self.drag_to(event.root_x() as i32, event.root_y() as i32);
+
+        // If a window was dropped against a screen edge, tile it there
+        if self.mouse_mode == MouseMode::Move {
+            self.drop_at_edge(event.root_x() as i32, event.root_y() as i32);
         }
 
+        // Hide the geometry readout
+        self.readout.hide(&self.conn);
+
         // Unselect the window and unset MouseMode
         self.selected = None;
         self.mouse_mode = MouseMode::Ground;
@@ -366,13 +797,66 @@ impl<'a> WM<'a> {
         self.conn.ungrab_pointer();
     }
 
+    fn drop_at_edge(&mut self, mouse_x: i32, mouse_y: i32) {
+        let screen = &self.screen.xwindow;
+
+        // Find which edge (if any) pointer is against
+        let edge = if mouse_x <= screen.x {
+            Direction::Left
+        } else if mouse_x >= screen.x + screen.width - 1 {
+            Direction::Right
+        } else if mouse_y <= screen.y {
+            Direction::Up
+        } else {
+            return;
+        };
+
+        // Get the selected window
+        if let Some(selected) = self.selected {
+            if let Some(idx) = self.desktop.current().windows.contains(selected) {
+                let selected = self.desktop.current_mut().windows.get_mut(idx).unwrap();
+
+                // Top edge maximises, left + right edges tile to that half
+                if edge == Direction::Up {
+                    selected.do_maximise(&self.conn, &self.screen);
+                } else {
+                    selected.do_tile_half(&self.conn, &self.screen, edge);
+                }
+            }
+        }
+    }
+
     fn on_key_press(&mut self, event: &xcb::KeyPressEvent) {
         // Decode KeyEvent
-        let (press_mask, press_key) = self.conn.lookup_keysym(event);
+        let (mut press_mask, press_key) = self.conn.lookup_keysym(event);
         debug!("on_key_press: {} {}", press_mask, press_key);
 
+        // Get binds for the current mode
+        let keybinds = match self.mode {
+            Some(idx) => {
+                // Escape always leaves a mode
+                if press_key == keysym::XK_Escape {
+                    self.mode_leave();
+                    return;
+                }
+
+                // One-shot modes (chords) end on the next key, bar modifiers still held from entering.
+                // MODKEY may still be down too, so match as if it isn't
+                let (_, oneshot, keybinds) = MODES[idx];
+                if oneshot {
+                    if keysyms::is_modifier_key(press_key) {
+                        return;
+                    }
+                    press_mask &= !MODKEY;
+                    self.mode_leave();
+                }
+                keybinds
+            },
+            None => KEYBINDS,
+        };
+
         // Try get function for keybind
-        for (mask, key, keyfn) in KEYBINDS {
+        for (mask, key, keyfn) in keybinds {
             // Check for match
             if *mask == press_mask && *key == press_key {
                 // If window id isn't the focused window id, refocus
@@ -387,8 +871,312 @@ impl<'a> WM<'a> {
         }
     }
 
+    fn on_mapping_notify(&mut self, event: &xcb::MappingNotifyEvent) {
+        debug!("on_mapping_notify: {}", event.request());
+
+        // Pointer button mapping changes don't affect us
+        if event.request() as u32 == xcb::MAPPING_POINTER {
+            return;
+        }
+
+        // Keycodes or modifiers have changed, so refresh the lookup table and redo grabs against it
+        if let Err(err) = self.conn.refresh_keyboard_mapping(event) {
+            warn!("Failed refreshing keyboard mapping: {}", err);
+        }
+        if let Err(err) = self.grab_keys() {
+            warn!("Leaving mode: {}", err);
+            self.mode_leave();
+        }
+        self.grab_buttons();
+    }
+
+    fn grab_buttons(&self) {
+        let root_id = self.screen.xwindow.id;
+
+        // Start afresh, as the lock key modifiers may have moved
+        self.conn.ungrab_buttons(root_id);
+        for (mask, button, _, _) in MOUSEBINDS {
+            self.conn.grab_button(root_id, helper::ROOT_BUTTON_GRAB_MASK, *button, *mask, true);
+        }
+    }
+
+    fn grab_keys(&self) -> Result<(), XError> {
+        let root_id = self.screen.xwindow.id;
+
+        // Start afresh, grabbing only the current mode's keys
+        self.conn.ungrab_keys(root_id);
+        self.conn.ungrab_keyboard();
+        let keybinds = match self.mode {
+            Some(idx) => {
+                let (_, oneshot, keybinds) = MODES[idx];
+
+                // One-shot modes take the whole keyboard, so any key can end them
+                if oneshot {
+                    return self.conn.grab_keyboard(root_id);
+                }
+
+                // Other modes need a way out
+                if let Err(err) = self.conn.grab_key(root_id, 0, keysym::XK_Escape) {
+                    warn!("Not grabbing key: {}", err);
+                }
+                keybinds
+            },
+            None => KEYBINDS,
+        };
+        for (mask, keysym, _) in keybinds {
+            if let Err(err) = self.conn.grab_key(root_id, *mask, *keysym) {
+                warn!("Not grabbing key: {}", err);
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn mode_enter(&mut self, name: &str) {
+        // Look up mode by name
+        let idx = match MODES.iter().position(|(mode, _, _)| *mode == name) {
+            Some(idx) => idx,
+            None => {
+                warn!("No such mode: {}", name);
+                return;
+            },
+        };
+        info!("Entering mode: {}", name);
+
+        // Switch grabs over, falling back to default binds if we can't (we'd otherwise be left with none)
+        self.mode = Some(idx);
+        if let Err(err) = self.grab_keys() {
+            warn!("Not entering mode {}: {}", name, err);
+            self.mode = None;
+            self.grab_keys().ok();
+            return;
+        }
+
+        // Tell bars
+        self.conn.set_mode(self.screen.xwindow.id, name);
+    }
+
+    pub fn mode_leave(&mut self) {
+        if self.mode.take().is_some() {
+            info!("Leaving mode");
+            self.grab_keys().ok();
+            self.conn.set_mode(self.screen.xwindow.id, DEFAULT_MODE);
+        }
+    }
+
     fn on_client_message(&mut self, event: &xcb::ClientMessageEvent) {
-        debug!("on_client_message: {} {}", event.window(), self.conn._get_atom_name(event.type_()));
+        debug!("on_client_message: {} {}", event.window(), self.conn._get_atom_name(event.type_()).unwrap_or_default());
+
+        // We only understand EWMH messages, which are all 32 bit
+        if event.format() != 32 {
+            return;
+        }
+        let data = event.data().data32();
+
+        if event.type_() == self.conn.atoms.WM_STATE {
+            // _NET_WM_STATE change request, data is: action, first property, second property
+            if data[1] == self.conn.atoms.WM_STATE_STICKY || data[2] == self.conn.atoms.WM_STATE_STICKY {
+                let sticky = match data[0] {
+                    ewmh::STATE_REMOVE => false,
+                    ewmh::STATE_ADD    => true,
+                    ewmh::STATE_TOGGLE => !self.is_sticky(event.window()),
+                    _ => return,
+                };
+                self.set_sticky(event.window(), sticky);
+            }
+        } else if event.type_() == self.conn.atoms.WM_CHANGE_STATE {
+            // ICCCM request to iconify, the only state change clients can ask for this way
+            if data[0] == icccm::WM_STATE_ICONIC as u32 {
+                self.window_minimize(event.window());
+            }
+        } else if event.type_() == self.conn.atoms.NUMBER_OF_DESKTOPS {
+            // Create / destroy workspaces at the end to match, keeping at least one
+            let number = (data[0] as usize).max(1);
+            debug!("Request for {} workspaces", number);
+            if number > MAX_WORKSPACES {
+                warn!("Ignoring request for {} workspaces, more than {}", number, MAX_WORKSPACES);
+                return;
+            }
+            while self.desktop.len() < number {
+                self.desktop.create("");
+            }
+            while self.desktop.len() > number {
+                self.desktop.destroy(&self.conn, &self.screen, self.desktop.len() - 1);
+            }
+            self.update_desktop_properties();
+            self.update_wm_desktops();
+        } else if event.type_() == self.conn.atoms.CURRENT_DESKTOP {
+            self.workspace_goto(data[0] as usize);
+        } else if event.type_() == self.conn.atoms.ACTIVE_WINDOW {
+            self.window_activate(event.window());
+        }
+    }
+
+    fn on_property_notify(&mut self, event: &xcb::PropertyNotifyEvent) {
+        // We only care about pagers renaming workspaces
+        if event.window() != self.screen.xwindow.id || event.atom() != self.conn.atoms.DESKTOP_NAMES {
+            return;
+        }
+        debug!("on_property_notify: desktop names");
+
+        // Take on any changed names. Not written back, that's where they came from
+        if let Ok(names) = self.conn.get_desktop_names(self.screen.idx) {
+            for (idx, name) in names.iter().enumerate().take(self.desktop.len()) {
+                if self.desktop.get(idx).name != *name {
+                    self.desktop.rename(idx, name);
+                }
+            }
+        }
+    }
+
+    pub fn window_minimize(&mut self, window_id: XWindowID) {
+        if let Some(ws_idx) = self.desktop.index_of(window_id) {
+            if self.desktop.get_mut(ws_idx).window_minimize(&self.conn, &self.screen, window_id) {
+                self.conn.set_wm_state_atom(window_id, self.conn.atoms.WM_STATE_HIDDEN, true);
+            }
+        }
+    }
+
+    pub fn window_restore(&mut self, window_id: XWindowID) {
+        // Restored windows come back on the current workspace, wherever they were minimized
+        if let Some(ws_idx) = self.desktop.hidden_index_of(window_id) {
+            let window = self.desktop.get_mut(ws_idx).hidden_take(window_id).unwrap();
+            self.desktop.current_mut().window_add(&self.conn, &self.screen, window);
+            self.conn.set_wm_state_atom(window_id, self.conn.atoms.WM_STATE_HIDDEN, false);
+            self.update_wm_desktop(window_id);
+        }
+    }
+
+    pub fn window_activate(&mut self, window_id: XWindowID) {
+        // Minimized windows are restored here, others are gone to wherever they are
+        if self.desktop.hidden_index_of(window_id).is_some() {
+            self.window_restore(window_id);
+        } else if let Some(idx) = self.desktop.index_of(window_id) {
+            self.workspace_goto(idx);
+        } else {
+            debug!("Not activating untracked window: {}", window_id);
+            return;
+        }
+        self.desktop.current_mut().window_focus(&self.conn, &self.screen, window_id);
+    }
+
+    pub fn workspace_goto(&mut self, idx: usize) {
+        self.desktop.goto(&self.conn, &self.screen, idx);
+        self.update_desktop_properties();
+    }
+
+    pub fn workspace_goto_previous(&mut self) {
+        self.desktop.goto_previous(&self.conn, &self.screen);
+        self.update_desktop_properties();
+    }
+
+    pub fn workspace_create(&mut self, name: &str) -> usize {
+        let idx = self.desktop.create(name);
+        self.update_desktop_properties();
+        return idx;
+    }
+
+    pub fn workspace_destroy(&mut self, idx: usize) {
+        if self.desktop.destroy(&self.conn, &self.screen, idx) {
+            self.update_desktop_properties();
+            self.update_wm_desktops();
+        }
+    }
+
+    fn update_desktop_properties(&self) {
+        // Tell pagers / bars about the workspaces
+        self.conn.set_number_of_desktops(self.screen.idx, self.desktop.len() as u32);
+        self.conn.set_desktop_names(self.screen.idx, &self.desktop.names());
+        self.conn.set_current_desktop(self.screen.idx, self.desktop.index() as u32);
+    }
+
+    fn update_active_window(&mut self) {
+        // Announce the current workspace's focused window, or none if empty
+        let window_id = self.desktop.current().windows.focused().map_or(xcb::NONE, |window| window.xwindow.id);
+        if window_id != self.active_window {
+            self.conn.set_active_window(self.screen.idx, window_id);
+            self.active_window = window_id;
+        }
+    }
+
+    fn update_wm_desktops(&self) {
+        // Workspace indices have shifted, so report for every window again
+        for ws in self.desktop.iter() {
+            for window in ws.windows.iter().chain(ws.hidden.iter()) {
+                self.update_wm_desktop(window.xwindow.id);
+            }
+        }
+    }
+
+    fn is_sticky(&self, window_id: XWindowID) -> bool {
+        return self.desktop.window(window_id).map_or(false, |window| window.sticky);
+    }
+
+    pub fn set_sticky(&mut self, window_id: XWindowID, sticky: bool) {
+        debug!("Setting window {} sticky: {}", window_id, sticky);
+
+        // Update the tracked window, pulling it onto the current workspace if need be
+        match self.desktop.window_mut(window_id) {
+            Some(window) => window.sticky = sticky,
+            None => {
+                debug!("set_sticky for untracked window: {}", window_id);
+                return;
+            },
+        }
+        let current = self.desktop.index();
+        if let Some(ws_idx) = self.desktop.index_of(window_id) {
+            // Minimized windows stay put until restored
+            let ws = self.desktop.get_mut(ws_idx);
+            if let Some(idx) = ws.windows.index_of(window_id) {
+                if sticky && ws_idx != current {
+                    let window = ws.window_del(&self.conn, &self.screen, idx, window_id);
+                    self.desktop.current_mut().window_add(&self.conn, &self.screen, window);
+                }
+            }
+        }
+
+        // Tell clients + pagers
+        self.conn.set_wm_state_atom(window_id, self.conn.atoms.WM_STATE_STICKY, sticky);
+        self.update_wm_desktop(window_id);
+    }
+
+    pub fn update_wm_desktop(&self, window_id: XWindowID) {
+        // Report the workspace a window is on, or all of them if sticky
+        if let Some(idx) = self.desktop.index_of(window_id) {
+            if self.is_sticky(window_id) {
+                self.conn.set_wm_desktop(window_id, ALL_DESKTOPS);
+            } else {
+                self.conn.set_wm_desktop(window_id, idx as u32);
+            }
+        }
+    }
+
+    fn on_selection_clear(&mut self, event: &xcb::SelectionClearEvent) {
+        // We only care about losing our window manager selection
+        if event.selection() != self.wm_selection || event.owner() != self.wm_window {
+            return;
+        }
+        info!("Replaced by another window manager, handing off");
+
+        // Map every tracked window so the new window manager can adopt those on inactive workspaces
+        for ws in self.desktop.iter() {
+            for window in ws.windows.iter().chain(ws.hidden.iter()) {
+                self.conn.map_window(window.xwindow.id);
+            }
+        }
+        for window in self.scratchpads.hidden() {
+            self.conn.map_window(window.xwindow.id);
+        }
+
+        // Let go of the root window, so the new window manager can select SubstructureRedirect on it
+        self.conn.change_window_attributes(self.screen.xwindow.id, &helper::values_attributes_no_events());
+        self.conn.flush();
+
+        // Destroy our selection window, signalling we have finished
+        self.conn.destroy_check_window(self.wm_window);
+        self.conn.flush();
+
+        // Exit via standard exit
+        process::exit(0);
     }
 
     pub fn kill(&mut self) {
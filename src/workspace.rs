@@ -1,12 +1,20 @@
 use crate::layout::{floating, LayoutType};
 use crate::screen::Screen;
-use crate::windows::{Window, Windows};
-use crate::x::{XConn, XWindowID};
+use crate::windows::{Direction, Window, Windows};
+use crate::x::{XBackend, XWindowID};
+
+use xcb_util::icccm;
 
 pub struct Workspace {
+    // Name shown by pagers / bars
+    pub name: String,
+
     // Internal window id tracking
     pub windows: Windows,
 
+    // Minimized windows, skipped by layouts. Most recently minimized last
+    pub hidden:  Vec<Window>,
+
     // Track if Workspace active (on-screen)
     pub active:  bool,
 
@@ -30,18 +38,21 @@ pub struct Workspace {
     // as it means performing a full re-layout on every single window change.
     //
     // If you're reading this and you have more ideas please do let me know, I'm open to them :p
-    _activate:             fn(&mut Workspace, &XConn, &Screen),
-    _deactivate:           fn(&mut Workspace, &XConn),
-    _window_add:           fn(&mut Workspace, &XConn, &Screen, Window),
-    _window_del:           fn(&mut Workspace, &XConn, &Screen, usize, XWindowID) -> Window,
-    _window_focus:         fn(&mut Workspace, &XConn, &Screen, XWindowID),
-    _window_focus_cycle:   fn(&mut Workspace, &XConn, &Screen),
+    _activate:             fn(&mut Workspace, &dyn XBackend, &Screen),
+    _deactivate:           fn(&mut Workspace, &dyn XBackend),
+    _window_add:           fn(&mut Workspace, &dyn XBackend, &Screen, Window),
+    _window_del:           fn(&mut Workspace, &dyn XBackend, &Screen, usize, XWindowID) -> Window,
+    _window_focus:         fn(&mut Workspace, &dyn XBackend, &Screen, XWindowID, bool),
+    _window_focus_cycle:   fn(&mut Workspace, &dyn XBackend, &Screen),
+    _window_swap:          fn(&mut Workspace, &dyn XBackend, &Screen, usize, usize),
 }
 
 impl Default for Workspace {
     fn default() -> Self {
         Self {
+            name:    String::new(),
             windows: Windows::default(),
+            hidden:  Vec::new(),
             active:  false,
 
             _activate: floating::activate,
@@ -50,12 +61,20 @@ impl Default for Workspace {
             _window_del: floating::window_del,
             _window_focus: floating::window_focus,
             _window_focus_cycle: floating::window_focus_cycle,
+            _window_swap: floating::window_swap,
         }
     }
 }
 
 impl Workspace {
-    pub fn set_layout(&mut self, conn: &XConn, screen: &Screen, t: LayoutType) {
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    pub fn set_layout(&mut self, conn: &dyn XBackend, screen: &Screen, t: LayoutType) {
         match t {
             LayoutType::Floating => {
                 debug!("Switching to layout: floating");
@@ -65,33 +84,89 @@ impl Workspace {
                 self._window_del = floating::window_del;
                 self._window_focus = floating::window_focus;
                 self._window_focus_cycle = floating::window_focus_cycle;
+                self._window_swap = floating::window_swap;
             },
         }
     }
 
-    pub fn activate(&mut self, conn: &XConn, screen: &Screen) {
+    pub fn activate(&mut self, conn: &dyn XBackend, screen: &Screen) {
         debug!("Activating workspace");
         (self._activate)(self, conn, screen);
         self.active = true;
+
+        // Windows may have arrived from elsewhere (e.g. sticky), so set every click grab afresh
+        for window in self.windows.iter().skip(1) {
+            conn.grab_click_to_focus(window.xwindow.id);
+        }
+        if let Some(focused) = self.windows.focused() {
+            conn.ungrab_click_to_focus(focused.xwindow.id);
+        }
+    }
+
+    fn focused_id(&self) -> Option<XWindowID> {
+        return self.windows.focused().map(|w| w.xwindow.id);
     }
 
-    pub fn deactivate(&mut self, conn: &XConn) {
+    fn update_click_grabs(&self, conn: &dyn XBackend, previous: Option<XWindowID>, raised: bool) {
+        // Unfocused windows grab clicks so a plain click focuses + raises them
+        let focused = self.focused_id();
+        if focused != previous {
+            if let Some(previous) = previous {
+                if self.windows.contains(previous).is_some() {
+                    conn.grab_click_to_focus(previous);
+                }
+            }
+        }
+
+        // Only the focused window goes without, once raised. Focused by hovering, a click must still raise it
+        if raised {
+            if let Some(focused) = focused {
+                conn.ungrab_click_to_focus(focused);
+            }
+        }
+    }
+
+    pub fn deactivate(&mut self, conn: &dyn XBackend) {
         debug!("Deactivating workspace");
         (self._deactivate)(self, conn);
         self.active = false;
     }
 
-    pub fn window_add(&mut self, conn: &XConn, screen: &Screen, window: Window) {
+    pub fn window_add(&mut self, conn: &dyn XBackend, screen: &Screen, window: Window) {
         debug!("Adding window to workspace: {}", window.xwindow.id);
-       (self._window_add)(self, conn, screen, window);
+        let previous = self.focused_id();
+        conn.grab_click_to_focus(window.xwindow.id);
+        (self._window_add)(self, conn, screen, window);
+        self.update_click_grabs(conn, previous, true);
     }
 
-    pub fn window_del(&mut self, conn: &XConn, screen: &Screen, idx: usize, window_id: XWindowID) -> Window {
+    pub fn window_del(&mut self, conn: &dyn XBackend, screen: &Screen, idx: usize, window_id: XWindowID) -> Window {
         debug!("Deleting window at index {} from workspace: {}", idx, window_id);
-        return (self._window_del)(self, conn, screen, idx, window_id);
+        let previous = self.focused_id();
+        let window = (self._window_del)(self, conn, screen, idx, window_id);
+        self.update_click_grabs(conn, previous, true);
+        return window;
+    }
+
+    pub fn window_minimize(&mut self, conn: &dyn XBackend, screen: &Screen, window_id: XWindowID) -> bool {
+        if let Some(idx) = self.windows.index_of(window_id) {
+            debug!("Minimizing window in workspace: {}", window_id);
+
+            // Remove through the layout
+            let window = self.window_del(conn, screen, idx, window_id);
+            conn.set_icccm_wm_state(window_id, icccm::WM_STATE_ICONIC);
+            self.hidden.push(window);
+            return true;
+        }
+        return false;
+    }
+
+    pub fn hidden_take(&mut self, window_id: XWindowID) -> Option<Window> {
+        let idx = self.hidden.iter().position(|w| w.xwindow.id == window_id)?;
+        return Some(self.hidden.remove(idx));
     }
 
-    pub fn window_del_focused(&mut self, conn: &XConn, screen: &Screen) -> Option<Window> {
+    pub fn window_del_focused(&mut self, conn: &dyn XBackend, screen: &Screen) -> Option<Window> {
         if let Some(focused) = self.windows.focused() {
             // Take ownership
             let focused = focused.to_owned();
@@ -105,13 +180,58 @@ impl Workspace {
         return None;
     }
 
-    pub fn window_focus(&mut self, conn: &XConn, screen: &Screen, window_id: XWindowID) {
+    pub fn window_focus(&mut self, conn: &dyn XBackend, screen: &Screen, window_id: XWindowID) {
         debug!("Focusing window in workspace: {}", window_id);
-        (self._window_focus)(self, conn, screen, window_id);
+        let previous = self.focused_id();
+        (self._window_focus)(self, conn, screen, window_id, true);
+        self.update_click_grabs(conn, previous, true);
     }
 
-    pub fn window_focus_cycle(&mut self, conn: &XConn, screen: &Screen) {
+    pub fn window_focus_no_raise(&mut self, conn: &dyn XBackend, screen: &Screen, window_id: XWindowID) {
+        debug!("Focusing window in workspace without raising: {}", window_id);
+        let previous = self.focused_id();
+        (self._window_focus)(self, conn, screen, window_id, false);
+        self.update_click_grabs(conn, previous, false);
+    }
+
+    pub fn window_unfocus(&mut self, conn: &dyn XBackend, screen: &Screen) {
+        debug!("Dropping focus to root window");
+        let previous = self.focused_id();
+        self.windows.unfocus();
+        conn.set_input_focus(screen.xwindow.id);
+        self.update_click_grabs(conn, previous, false);
+    }
+
+    pub fn window_focus_cycle(&mut self, conn: &dyn XBackend, screen: &Screen) {
         debug!("Cycling focused window");
+        let previous = self.focused_id();
         (self._window_focus_cycle)(self, conn, screen);
+        self.update_click_grabs(conn, previous, true);
     }
-}
\ No newline at end of file
+
+    pub fn window_focus_previous(&mut self, conn: &dyn XBackend, screen: &Screen) {
+        // Second most recently used window is the previously focused
+        if let Some(window) = self.windows.get(1) {
+            debug!("Focusing previous window");
+            let window_id = window.xwindow.id;
+            self.window_focus(conn, screen, window_id);
+        }
+    }
+
+    pub fn window_focus_direction(&mut self, conn: &dyn XBackend, screen: &Screen, direction: Direction) {
+        // Find nearest window to focused in direction, and focus
+        if let Some(idx) = self.windows.nearest_in_direction(0, direction) {
+            debug!("Focusing window in direction: {:?}", direction);
+            let window_id = self.windows.get(idx).unwrap().xwindow.id;
+            self.window_focus(conn, screen, window_id);
+        }
+    }
+
+    pub fn window_swap_direction(&mut self, conn: &dyn XBackend, screen: &Screen, direction: Direction) {
+        // Find nearest window to focused in direction, and swap
+        if let Some(idx) = self.windows.nearest_in_direction(0, direction) {
+            debug!("Swapping focused window in direction: {:?}", direction);
+            (self._window_swap)(self, conn, screen, 0, idx);
+        }
+    }
+}
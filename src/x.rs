@@ -1,15 +1,109 @@
 use crate::helper;
 use crate::windows::Window;
 
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
 use xcb_util::{cursor, ewmh, icccm};
 use xcb_util::keysyms::KeySymbols;
+use x11::keysym;
 
+#[derive(Clone, Copy)]
 pub enum CursorIndex {
     LeftPtr,
+    TopLeftCorner,
+    TopSide,
+    TopRightCorner,
+    RightSide,
+    BottomRightCorner,
+    BottomSide,
+    BottomLeftCorner,
+    LeftSide,
+    Fleur,
 }
 
+// Number of CursorIndex variants
+const CURSORS: usize = 10;
+
 pub type XWindowID = xcb::Window;
 
+// X protocol error codes we react to
+pub const ERROR_BAD_WINDOW: u8 = 3;
+pub const ERROR_BAD_DRAWABLE: u8 = 9;
+
+#[derive(Debug)]
+pub enum XError {
+    // Connection to the X server failed or was lost
+    Connection(xcb::ConnError),
+
+    // X server replied to a request with a protocol error
+    Protocol { error_code: u8, resource_id: u32, major_code: u8 },
+
+    // Screen index has no matching root window
+    InvalidScreen(i32),
+
+    // Another window manager owns the WM_S<screen> selection
+    OtherWMRunning(i32),
+
+    // Failed to take over the WM_S<screen> selection
+    SelectionFailed(i32),
+
+    // Keysym has no keycode in the current keyboard mapping
+    NoKeycode(xcb::Keysym),
+
+    // Active keyboard grab refused, e.g. another client holds the keyboard
+    GrabFailed(u8),
+}
+
+impl XError {
+    pub fn from_generic(err: &xcb::GenericError) -> Self {
+        // Read the extra error fields not exposed by xcb::GenericError
+        let raw = unsafe { &*(err.ptr as *const xcb::ffi::base::xcb_generic_error_t) };
+        return XError::Protocol {
+            error_code: raw.error_code,
+            resource_id: raw.resource_id,
+            major_code: raw.major_code,
+        };
+    }
+
+    pub fn is_bad_window(&self) -> bool {
+        match self {
+            XError::Protocol { error_code, .. } => return *error_code == ERROR_BAD_WINDOW || *error_code == ERROR_BAD_DRAWABLE,
+            _ => return false,
+        }
+    }
+}
+
+impl From<xcb::GenericError> for XError {
+    fn from(err: xcb::GenericError) -> Self {
+        return XError::from_generic(&err);
+    }
+}
+
+impl From<xcb::ConnError> for XError {
+    fn from(err: xcb::ConnError) -> Self {
+        return XError::Connection(err);
+    }
+}
+
+impl fmt::Display for XError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            XError::Connection(err) => write!(f, "X server connection error: {}", err),
+            XError::Protocol { error_code, resource_id, major_code } => write!(f, "X protocol error {} for resource {} (request opcode {})", error_code, resource_id, major_code),
+            XError::InvalidScreen(idx) => write!(f, "No root window for screen {}", idx),
+            XError::OtherWMRunning(idx) => write!(f, "Another window manager is already running on screen {} (use --replace to take over)", idx),
+            XError::SelectionFailed(idx) => write!(f, "Failed to acquire window manager selection on screen {}", idx),
+            XError::NoKeycode(keysym) => write!(f, "Keysym {} has no keycode in the current keyboard mapping", keysym),
+            XError::GrabFailed(status) => write!(f, "Keyboard grab failed with status {}", status),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct XWindow {
     pub id: XWindowID,
@@ -41,9 +135,13 @@ impl From<XWindowID> for XWindow {
 }
 
 impl XWindow {
-    pub fn update_geometry(&mut self, conn: &XConn) {
+    pub fn centre(&self) -> (i32, i32) {
+        return (self.x + self.width / 2, self.y + self.height / 2);
+    }
+
+    pub fn update_geometry(&mut self, conn: &dyn XBackend) {
         // Attempt to get window geometry, and set!
-        if let Some((x, y, width, height)) = conn.get_geometry(self.id) {
+        if let Ok((x, y, width, height)) = conn.get_geometry(self.id) {
             self.x = x;
             self.y = y;
             self.width = width;
@@ -53,6 +151,7 @@ impl XWindow {
 }
 
 pub struct InternedAtoms {
+    pub MANAGER:                xcb::Atom,
     pub SUPPORTED:              xcb::Atom,
     pub WM_DELETE_WINDOW:       xcb::Atom,
     pub WM_PROTOCOLS:           xcb::Atom,
@@ -61,60 +160,149 @@ pub struct InternedAtoms {
     pub WM_WINDOW_TYPE_TOOLBAR: xcb::Atom,
     pub WM_WINDOW_TYPE_UTILITY: xcb::Atom,
     pub WM_WINDOW_TYPE_SPLASH:  xcb::Atom,
+    pub WM_STATE:               xcb::Atom,
+    pub WM_STATE_STICKY:        xcb::Atom,
+    pub WM_DESKTOP:             xcb::Atom,
+    pub WM_STATE_HIDDEN:        xcb::Atom,
+    pub WM_CHANGE_STATE:        xcb::Atom,
+    pub ICCCM_WM_STATE:         xcb::Atom, // ICCCM WM_STATE, as WM_STATE is the EWMH _NET_WM_STATE
+    pub AFWM_MODE:              xcb::Atom,
+    pub UTF8_STRING:            xcb::Atom,
+    pub NUMBER_OF_DESKTOPS:     xcb::Atom,
+    pub CURRENT_DESKTOP:        xcb::Atom,
+    pub DESKTOP_NAMES:          xcb::Atom,
+    pub ACTIVE_WINDOW:          xcb::Atom,
 }
 
 impl InternedAtoms {
-    fn new(conn: &ewmh::Connection) -> Self {
-        Self {
+    fn new(conn: &ewmh::Connection) -> Result<Self, XError> {
+        Ok(Self {
+            MANAGER:                xcb::intern_atom(conn, false, "MANAGER").get_reply()?.atom(),
             SUPPORTED:              conn.SUPPORTED(),
-            WM_DELETE_WINDOW:       xcb::intern_atom(conn, false, "WM_DELETE_WINDOW").get_reply().expect("Interning WM_DELETE_WINDOW atom").atom(),
+            WM_DELETE_WINDOW:       xcb::intern_atom(conn, false, "WM_DELETE_WINDOW").get_reply()?.atom(),
             WM_PROTOCOLS:           conn.WM_PROTOCOLS(),
             WM_WINDOW_TYPE_NORMAL:  conn.WM_WINDOW_TYPE_NORMAL(),
             WM_WINDOW_TYPE_DIALOG:  conn.WM_WINDOW_TYPE_DIALOG(),
             WM_WINDOW_TYPE_TOOLBAR: conn.WM_WINDOW_TYPE_TOOLBAR(),
             WM_WINDOW_TYPE_UTILITY: conn.WM_WINDOW_TYPE_UTILITY(),
             WM_WINDOW_TYPE_SPLASH:  conn.WM_WINDOW_TYPE_SPLASH(),
-        }
+            WM_STATE:               conn.WM_STATE(),
+            WM_STATE_STICKY:        conn.WM_STATE_STICKY(),
+            WM_DESKTOP:             conn.WM_DESKTOP(),
+            WM_STATE_HIDDEN:        conn.WM_STATE_HIDDEN(),
+            WM_CHANGE_STATE:        xcb::intern_atom(conn, false, "WM_CHANGE_STATE").get_reply()?.atom(),
+            ICCCM_WM_STATE:         xcb::intern_atom(conn, false, "WM_STATE").get_reply()?.atom(),
+            AFWM_MODE:              xcb::intern_atom(conn, false, "_AFWM_MODE").get_reply()?.atom(),
+            UTF8_STRING:            xcb::intern_atom(conn, false, "UTF8_STRING").get_reply()?.atom(),
+            NUMBER_OF_DESKTOPS:     conn.NUMBER_OF_DESKTOPS(),
+            CURRENT_DESKTOP:        conn.CURRENT_DESKTOP(),
+            DESKTOP_NAMES:          conn.DESKTOP_NAMES(),
+            ACTIVE_WINDOW:          conn.ACTIVE_WINDOW(),
+        })
     }
 }
 
+// Requests the window management logic (workspaces, layouts, windows) needs from X.
+// XConn implements this against a live server, tests implement it with a recording mock
+pub trait XBackend {
+    fn map_window(&self, window_id: XWindowID);
+    fn unmap_window(&self, window_id: XWindowID);
+    fn configure_window(&self, window_id: XWindowID, values: &[(u16, u32)]);
+    fn change_window_attributes(&self, window_id: XWindowID, values: &[(u32, u32)]);
+    fn set_icccm_wm_state(&self, window_id: XWindowID, state: icccm::WmState);
+    fn set_input_focus(&self, window_id: XWindowID);
+    fn grab_click_to_focus(&self, window_id: XWindowID);
+    fn ungrab_click_to_focus(&self, window_id: XWindowID);
+    fn destroy_window(&self, window: &Window);
+    fn get_geometry(&self, window_id: XWindowID) -> Result<(i32, i32, i32, i32), XError>;
+    fn get_wm_protocols(&self, window_id: XWindowID) -> Result<Vec<xcb::Atom>, XError>;
+}
+
 pub struct XConn<'a> {
     // X server connection
     pub conn: &'a ewmh::Connection,
 
     // Stored loaded cursor ids
-    cursors: [u32; 1],
+    cursors: [u32; CURSORS],
 
     // KeySymbol lookup object
     key_syms: KeySymbols<'a>,
 
     // Interned atoms
     pub atoms: InternedAtoms,
+
+    // Events read ahead of time, e.g. held back while compressing motion or waiting for a specific event
+    pending_events: VecDeque<xcb::GenericEvent>,
+
+    // Modifier bits of the lock keys (CapsLock, NumLock, ScrollLock), ignored in binds
+    lock_mask: xcb::ModMask,
 }
 
 impl<'a> XConn<'a> {
-    pub fn new(conn: &'a ewmh::Connection) -> Self {
+    pub fn new(conn: &'a ewmh::Connection) -> Result<Self, XError> {
         // Create new atoms object
-        let atoms = InternedAtoms::new(conn);
+        let atoms = InternedAtoms::new(conn)?;
 
         // Create new Self
-        let new = Self {
+        let mut new = Self {
             conn:     conn,
-            cursors:  [0; 1],
+            cursors:  [0; CURSORS],
             key_syms: KeySymbols::new(conn),
             atoms:    atoms,
+            pending_events: VecDeque::new(),
+            lock_mask: xcb::MOD_MASK_LOCK,
         };
 
+        // Find the modifiers NumLock and ScrollLock are mapped to
+        new.update_lock_mask()?;
+
         // Return the new Self
-        return new;
+        return Ok(new);
     }
 
-    pub fn create_core_cursor(&mut self, cursor: CursorIndex, cursor_glyph: u16) {
+    pub fn update_lock_mask(&mut self) -> Result<(), XError> {
+        // CapsLock is always Lock, but NumLock and ScrollLock can sit on any of Mod1-5
+        let mut lock_mask = xcb::MOD_MASK_LOCK;
+        let reply = xcb::get_modifier_mapping(self.conn).get_reply()?;
+        let per_modifier = reply.keycodes_per_modifier() as usize;
+        for keysym in &[keysym::XK_Num_Lock, keysym::XK_Scroll_Lock] {
+            let codes: Vec<xcb::Keycode> = self.key_syms.get_keycode(*keysym).collect();
+            for (idx, modifier) in reply.keycodes().chunks(per_modifier).enumerate() {
+                if modifier.iter().any(|code| *code != 0 && codes.contains(code)) {
+                    lock_mask |= 1 << idx;
+                }
+            }
+        }
+
+        debug!("Lock key modifier mask: {}", lock_mask);
+        self.lock_mask = lock_mask;
+        return Ok(());
+    }
+
+    pub fn refresh_keyboard_mapping(&mut self, event: &xcb::MappingNotifyEvent) -> Result<(), XError> {
+        // Drop cached keycodes for the changed range, then recheck where the lock keys now sit
+        self.key_syms.refresh_keyboard_mapping(event);
+        return self.update_lock_mask();
+    }
+
+    fn lock_combinations(&self) -> Vec<xcb::ModMask> {
+        // Every subset of the lock bits, so binds work whichever locks are on
+        let mut combinations = vec![0];
+        for bit in (0..8).map(|idx| 1 << idx).filter(|bit| self.lock_mask & bit != 0) {
+            for idx in 0..combinations.len() {
+                combinations.push(combinations[idx] | bit);
+            }
+        }
+        return combinations;
+    }
+
+    pub fn create_core_cursor(&mut self, cursor: CursorIndex, cursor_glyph: u16) -> Result<(), XError> {
         // Try load cursor for supplied cursor glyp
-        let cursor_id = cursor::create_font_cursor_checked(self.conn, cursor_glyph).expect("Creating font cursor");
+        let cursor_id = cursor::create_font_cursor_checked(self.conn, cursor_glyph)?;
 
         // Store the cursor id in the cursors array at supplied index
         self.cursors[cursor as usize] = cursor_id;
+        return Ok(());
     }
 
     pub fn set_cursor(&mut self, window_id: XWindowID, cursor: CursorIndex) {
@@ -130,135 +318,317 @@ impl<'a> XConn<'a> {
         ewmh::set_supported(self.conn, screen_idx, &atoms);
     }
 
-    pub fn get_setup(&self) -> xcb::Setup {
-        debug!("Getting setup");
+    pub fn set_number_of_desktops(&self, screen_idx: i32, number: u32) {
+        debug!("Setting number of desktops: {}", number);
+        ewmh::set_number_of_desktops(self.conn, screen_idx, number);
+    }
 
-        // Return the current X connection's setup
-        return self.conn.get_setup();
+    pub fn set_current_desktop(&self, screen_idx: i32, idx: u32) {
+        debug!("Setting current desktop: {}", idx);
+        ewmh::set_current_desktop(self.conn, screen_idx, idx);
     }
 
-    pub fn query_tree(&self, window_id: XWindowID) -> Vec<XWindowID> {
-        debug!("Querying tree");
+    pub fn set_active_window(&self, screen_idx: i32, window_id: XWindowID) {
+        debug!("Setting active window: {}", window_id);
+        ewmh::set_active_window(self.conn, screen_idx, window_id);
+    }
 
-        // Query tree for window. Don't bother checking returning option, we only ever query tree for root (for now...)
-        return xcb::query_tree(self.conn, window_id).get_reply().expect("Querying tree").children().to_owned();
+    pub fn set_desktop_names(&self, screen_idx: i32, names: &[&str]) {
+        debug!("Setting desktop names: {:?}", names);
+        ewmh::set_desktop_names(self.conn, screen_idx, names.iter().copied());
     }
 
-    pub fn map_window(&self, window_id: XWindowID) {
-        debug!("Mapping window: {}", window_id);
+    pub fn get_desktop_names(&self, screen_idx: i32) -> Result<Vec<String>, XError> {
+        debug!("Getting desktop names");
+        return Ok(ewmh::get_desktop_names(self.conn, screen_idx).get_reply()?.strings().iter().map(|name| name.to_string()).collect());
+    }
 
-        // Map window. Don't bother checking, if it failed, it failed :shrug:
-        xcb::map_window(self.conn, window_id);
+    pub fn intern_atom(&self, name: &str) -> Result<xcb::Atom, XError> {
+        debug!("Interning atom: {}", name);
+
+        // Intern the atom
+        return Ok(xcb::intern_atom(self.conn, false, name).get_reply()?.atom());
     }
 
-    pub fn unmap_window(&self, window_id: XWindowID) {
-        debug!("Unmapping window: {}", window_id);
+    pub fn get_selection_owner(&self, selection: xcb::Atom) -> Result<XWindowID, XError> {
+        debug!("Getting selection owner");
 
-        // Unmap window. Don't bother checking, if it failed, it failed :shrug:
-        xcb::unmap_window(self.conn, window_id);
+        // Get the current selection owner (or xcb::NONE)
+        return Ok(xcb::get_selection_owner(self.conn, selection).get_reply()?.owner());
     }
 
-    pub fn configure_window(&self, window_id: XWindowID, values: &[(u16, u32)]) {
-        debug!("Configuring window: {}", window_id);
+    pub fn set_selection_owner(&self, window_id: XWindowID, selection: xcb::Atom, time: xcb::Timestamp) {
+        debug!("Setting selection owner: {}", window_id);
 
-        // Configure window. Don't bother checking, if it failed, it failed :shrug:
-        xcb::configure_window(self.conn, window_id, values);
+        // Set selection owner. Success is checked by the caller via get_selection_owner()
+        xcb::set_selection_owner(self.conn, window_id, selection, time);
     }
 
-    pub fn change_window_attributes(&self, window_id: XWindowID, values: &[(u32, u32)]) {
-        debug!("Changing window attributes: {}", window_id);
+    pub fn create_check_window(&self, parent_id: XWindowID) -> XWindowID {
+        // Generate new window id
+        let window_id = self.conn.generate_id();
+        debug!("Creating check window: {}", window_id);
 
-        // Change window attributes. Don't bother checking, if it failed, it failed :shrug:
-        xcb::change_window_attributes(self.conn, window_id, values);
+        // Create a small, unmapped input-only window we own (used as selection owner)
+        xcb::create_window(
+            self.conn,
+            0,                                     // depth (copy from parent)
+            window_id,                             // window id
+            parent_id,                             // parent window id
+            -1, -1,                                // x, y
+            1, 1,                                  // width, height
+            0,                                     // border width
+            xcb::WINDOW_CLASS_INPUT_ONLY as u16,   // window class
+            0,                                     // visual (copy from parent)
+            &helper::values_attributes_check_window(),
+        );
+
+        // Return the new window id
+        return window_id;
     }
 
-    pub fn change_window_attributes_checked(&self, window_id: XWindowID, values: &[(u32, u32)]) {
-        debug!("Changing window attributes: {}", window_id);
+    pub fn create_readout_window(&self, screen_idx: i32, parent_id: XWindowID, width: u16, height: u16) -> XWindowID {
+        // Generate new window id
+        let window_id = self.conn.generate_id();
+        debug!("Creating readout window: {}", window_id);
 
-        // Change window attributes, ensure it goes through okay!
-        xcb::change_window_attributes_checked(self.conn, window_id, values).request_check().expect("Changing window attributes");
+        // Create an unmapped, override-redirect window we can draw text into
+        xcb::create_window(
+            self.conn,
+            0,                                     // depth (copy from parent)
+            window_id,                             // window id
+            parent_id,                             // parent window id
+            0, 0,                                  // x, y
+            width, height,                         // width, height
+            1,                                     // border width
+            xcb::WINDOW_CLASS_INPUT_OUTPUT as u16, // window class
+            0,                                     // visual (copy from parent)
+            &helper::values_attributes_readout_window(self.black_pixel(screen_idx), self.white_pixel(screen_idx)),
+        );
+
+        // Return the new window id
+        return window_id;
     }
 
-    pub fn set_input_focus(&self, window_id: XWindowID) {
-        debug!("Setting input focus window: {}", window_id);
+    pub fn create_text_gc(&self, screen_idx: i32, drawable: xcb::Drawable, font_name: &str) -> Result<xcb::Gcontext, XError> {
+        debug!("Creating text graphics context with font: {}", font_name);
 
-        // Set input focus on window. Don't bother checking, if it failed, it failed :shrug:
-        xcb::set_input_focus(self.conn, xcb::INPUT_FOCUS_POINTER_ROOT as u8, window_id, xcb::CURRENT_TIME);
+        // Open the font, ensuring it exists
+        let font_id = self.conn.generate_id();
+        xcb::open_font_checked(self.conn, font_id, font_name).request_check()?;
+
+        // Create graphics context drawing with the font, white on black
+        let gc_id = self.conn.generate_id();
+        xcb::create_gc(self.conn, gc_id, drawable, &[
+            (xcb::GC_FOREGROUND, self.white_pixel(screen_idx)),
+            (xcb::GC_BACKGROUND, self.black_pixel(screen_idx)),
+            (xcb::GC_FONT, font_id),
+        ]);
+
+        // Font can be closed now, the graphics context keeps a reference
+        xcb::close_font(self.conn, font_id);
+
+        return Ok(gc_id);
     }
 
-    pub fn destroy_window(&self, window: &Window) {
-        debug!("Destroying window: {}", window.xwindow.id);
+    pub fn draw_text(&self, window_id: XWindowID, gc_id: xcb::Gcontext, x: i16, y: i16, text: &str) {
+        // Clear the whole window, then draw the text. Errors are delivered via the event queue and handled in WM::run()
+        xcb::clear_area(self.conn, false, window_id, 0, 0, 0, 0);
+        xcb::image_text_8(self.conn, window_id, gc_id, x, y, text);
+    }
 
-        // Check how best to destroy window
-        if window.supports_protocol(&self.atoms.WM_DELETE_WINDOW) {
-            // Window support ICCCM method of WM_DELETE_WINDOW
-            debug!("Destroy window via ICCCM WM_DELETE_WINDOW");
+    fn black_pixel(&self, screen_idx: i32) -> u32 {
+        return self.conn.get_setup().roots().nth(screen_idx as usize).map_or(0, |screen| screen.black_pixel());
+    }
 
-            // Create client message data
-            let msg_data = xcb::ClientMessageData::from_data32([self.atoms.WM_DELETE_WINDOW, xcb::CURRENT_TIME, 0, 0, 0]);
+    fn white_pixel(&self, screen_idx: i32) -> u32 {
+        return self.conn.get_setup().roots().nth(screen_idx as usize).map_or(0xffffff, |screen| screen.white_pixel());
+    }
 
-            // Create event from message data
-            let event = xcb::ClientMessageEvent::new(32, window.xwindow.id, self.atoms.WM_PROTOCOLS, msg_data);
+    pub fn get_timestamp(&mut self, window_id: XWindowID) -> Result<xcb::Timestamp, XError> {
+        debug!("Getting server timestamp via window: {}", window_id);
 
-            // Send the event!
-            xcb::send_event(
-                self.conn,                // connection
-                false,                    // propagate?
-                window.xwindow.id,        // destination window
-                xcb::EVENT_MASK_NO_EVENT, // event mask
-                &event,                   // event object
-            );
-        } else {
-            // Use plain-old X destroy window
-            debug!("Destroy window via xcb_destroy_window");
-            xcb::destroy_window(self.conn, window.xwindow.id);
+        // Perform a zero-length property append, the resulting PropertyNotify carries the server time
+        xcb::change_property(self.conn, xcb::PROP_MODE_APPEND as u8, window_id, xcb::ATOM_WM_NAME, xcb::ATOM_STRING, 8, &[] as &[u8]);
+        self.conn.flush();
+
+        // Wait for the PropertyNotify, keeping any other events for later
+        loop {
+            let event = self.wait_for_event()?;
+            if event.response_type() & !0x80 == xcb::PROPERTY_NOTIFY {
+                let notify: &xcb::PropertyNotifyEvent = unsafe { xcb::cast_event(&event) };
+                if notify.window() == window_id {
+                    return Ok(notify.time());
+                }
+            }
+            self.pending_events.push_back(event);
         }
     }
 
-    pub fn grab_key(&self, window_id: XWindowID, mask: xcb::ModMask, keysym: xcb::Keysym) {
-        debug!("Grabbing key with mask:{} sym:{} for window: {}", mask, keysym, window_id);
+    pub fn wait_for_destroy(&mut self, window_id: XWindowID, timeout: Duration) -> bool {
+        debug!("Waiting for window to be destroyed: {}", window_id);
 
-        // Get code iter for keysym
-        let code = self.key_syms.get_keycode(keysym).next();
+        // Ensure any requests are sent before waiting
+        self.conn.flush();
 
-        // If no code, log and move-on. Else, unwrap
-        if code.is_none() {
-            warn!("Keysym {} translated to zero-length keycode iter, not grabbing", keysym);
-            return;
+        // Poll events until we see the window's DestroyNotify, or time out, keeping any other events for later
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if let Some(event) = self.conn.poll_for_event() {
+                if event.response_type() & !0x80 == xcb::DESTROY_NOTIFY {
+                    let notify: &xcb::DestroyNotifyEvent = unsafe { xcb::cast_event(&event) };
+                    if notify.window() == window_id {
+                        return true;
+                    }
+                }
+                self.pending_events.push_back(event);
+            } else {
+                thread::sleep(Duration::from_millis(10));
+            }
         }
-        let code = code.unwrap();
+        return false;
+    }
 
-        // Register key code to grab with X. We don't bother checking as only ever for root window
-        xcb::grab_key(
-            self.conn,
-            false,                       // owner events (a.k.a don't pass on events to root window)
-            window_id,                   // window id
-            mask as u16,                 // key mod mask
-            code,                        // keycode
-            xcb::GRAB_MODE_ASYNC as u8,  // pointer mode
-            xcb::GRAB_MODE_ASYNC as u8   // keyboard mode
+    pub fn send_manager_message(&self, root_id: XWindowID, time: xcb::Timestamp, selection: xcb::Atom, owner_id: XWindowID) {
+        debug!("Announcing new selection owner {} via MANAGER message", owner_id);
+
+        // Create client message data as defined in ICCCM section 2.8
+        let msg_data = xcb::ClientMessageData::from_data32([time, selection, owner_id, 0, 0]);
+
+        // Create event from message data
+        let event = xcb::ClientMessageEvent::new(32, root_id, self.atoms.MANAGER, msg_data);
+
+        // Send the event to all clients listening on root window
+        xcb::send_event(
+            self.conn,                        // connection
+            false,                            // propagate?
+            root_id,                          // destination window
+            xcb::EVENT_MASK_STRUCTURE_NOTIFY, // event mask
+            &event,                           // event object
         );
     }
 
+    pub fn flush(&self) {
+        // Flush all pending requests to the X server
+        self.conn.flush();
+    }
+
+    pub fn get_setup(&self) -> xcb::Setup {
+        debug!("Getting setup");
+
+        // Return the current X connection's setup
+        return self.conn.get_setup();
+    }
+
+    pub fn query_tree(&self, window_id: XWindowID) -> Result<Vec<XWindowID>, XError> {
+        debug!("Querying tree");
+
+        // Query tree for window
+        return Ok(xcb::query_tree(self.conn, window_id).get_reply()?.children().to_owned());
+    }
+
+    pub fn change_window_attributes_checked(&self, window_id: XWindowID, values: &[(u32, u32)]) -> Result<(), XError> {
+        debug!("Changing window attributes: {}", window_id);
+
+        // Change window attributes, ensure it goes through okay!
+        xcb::change_window_attributes_checked(self.conn, window_id, values).request_check()?;
+        return Ok(());
+    }
+
+    pub fn destroy_check_window(&self, window_id: XWindowID) {
+        debug!("Destroying check window: {}", window_id);
+
+        // Destroy our own window, releasing any selections it owns
+        xcb::destroy_window(self.conn, window_id);
+    }
+
+    pub fn grab_key(&self, window_id: XWindowID, mask: xcb::ModMask, keysym: xcb::Keysym) -> Result<(), XError> {
+        debug!("Grabbing key with mask:{} sym:{} for window: {}", mask, keysym, window_id);
+
+        // Get all codes for keysym, if none we can't grab it
+        let codes: Vec<xcb::Keycode> = self.key_syms.get_keycode(keysym).collect();
+        if codes.is_empty() {
+            return Err(XError::NoKeycode(keysym));
+        }
+
+        // Register each key code to grab with X, once per lock key combination. We don't bother checking as only ever for root window
+        for code in codes {
+            for locks in self.lock_combinations() {
+                xcb::grab_key(
+                    self.conn,
+                    false,                       // owner events (a.k.a don't pass on events to root window)
+                    window_id,                   // window id
+                    (mask | locks) as u16,       // key mod mask
+                    code,                        // keycode
+                    xcb::GRAB_MODE_ASYNC as u8,  // pointer mode
+                    xcb::GRAB_MODE_ASYNC as u8   // keyboard mode
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn ungrab_keys(&self, window_id: XWindowID) {
+        debug!("Ungrabbing all keys for window: {}", window_id);
+        xcb::ungrab_key(self.conn, xcb::GRAB_ANY as u8, window_id, xcb::MOD_MASK_ANY as u16);
+    }
+
+    pub fn grab_keyboard(&self, window_id: XWindowID) -> Result<(), XError> {
+        debug!("Grabbing keyboard for window: {}", window_id);
+
+        // Actively grab every key press, e.g. to end a chord on whatever comes next
+        let reply = xcb::grab_keyboard(self.conn, false, window_id, xcb::CURRENT_TIME, xcb::GRAB_MODE_ASYNC as u8, xcb::GRAB_MODE_ASYNC as u8).get_reply()?;
+        if reply.status() as u32 != xcb::GRAB_STATUS_SUCCESS {
+            return Err(XError::GrabFailed(reply.status()));
+        }
+        return Ok(());
+    }
+
+    pub fn ungrab_keyboard(&self) {
+        debug!("Ungrabbing keyboard");
+        xcb::ungrab_keyboard(self.conn, xcb::CURRENT_TIME);
+    }
+
+    pub fn set_mode(&self, window_id: XWindowID, mode: &str) {
+        debug!("Setting mode property: {}", mode);
+
+        // Announce the keybinding mode as a UTF-8 string property, for bars to watch
+        xcb::change_property(self.conn, xcb::PROP_MODE_REPLACE as u8, window_id, self.atoms.AFWM_MODE, self.atoms.UTF8_STRING, 8, mode.as_bytes());
+    }
+
     pub fn grab_button(&self, window_id: XWindowID, mask: xcb::ButtonMask, button: xcb::ButtonIndex, modmask: xcb::ModMask, confine: bool) {
         debug!("Grabbing button {} for window: {}", window_id, button);
 
-        // Register button to grab with X. We don't bother checking as only ever for root window
-        xcb::grab_button(
-            self.conn,
-            false,                                       // owner events (a.k. don't pass on events to root window)
-            window_id,                                   // window id
-            mask as u16,                                 // button event mask
-            xcb::GRAB_MODE_ASYNC as u8,                  // pointer mode
-            xcb::GRAB_MODE_ASYNC as u8,                  // keyboard mode
-            if confine { window_id } else { xcb::NONE }, // confine pointer to window (or no confine)
-            xcb::NONE,                                   // cursor to use
-            button as u8,                                // button to grab (right click)
-            modmask as u16,                              // Modifiers to grab mouse with
-        );
+        // Register button to grab with X, once per lock key combination. We don't bother checking as only ever for root window
+        for locks in self.lock_combinations() {
+            xcb::grab_button(
+                self.conn,
+                false,                                       // owner events (a.k. don't pass on events to root window)
+                window_id,                                   // window id
+                mask as u16,                                 // button event mask
+                xcb::GRAB_MODE_ASYNC as u8,                  // pointer mode
+                xcb::GRAB_MODE_ASYNC as u8,                  // keyboard mode
+                if confine { window_id } else { xcb::NONE }, // confine pointer to window (or no confine)
+                xcb::NONE,                                   // cursor to use
+                button as u8,                                // button to grab (right click)
+                (modmask | locks) as u16,                    // Modifiers to grab mouse with
+            );
+        }
+    }
+
+    pub fn ungrab_buttons(&self, window_id: XWindowID) {
+        debug!("Ungrabbing all buttons for window: {}", window_id);
+        xcb::ungrab_button(self.conn, xcb::BUTTON_INDEX_ANY as u8, window_id, xcb::MOD_MASK_ANY as u16);
     }
 
-    pub fn grab_pointer(&self, window_id: XWindowID, mask: xcb::EventMask) {
+    pub fn allow_events_replay_pointer(&self) {
+        debug!("Replaying grabbed pointer event");
+
+        // Release the synchronous grab, passing the click on to the client
+        xcb::allow_events(self.conn, xcb::ALLOW_REPLAY_POINTER as u8, xcb::CURRENT_TIME);
+    }
+
+    pub fn grab_pointer(&self, window_id: XWindowID, mask: xcb::EventMask, cursor: CursorIndex) {
         debug!("Grabbing pointer for window: {}", window_id);
 
         // Register to grab pointer. We don't bother checking as only ever for root window
@@ -270,7 +640,7 @@ impl<'a> XConn<'a> {
             xcb::GRAB_MODE_ASYNC as u8,                  // pointer mode
             xcb::GRAB_MODE_ASYNC as u8,                  // keyboard mode
             xcb::NONE,                                   // confine to window
-            xcb::NONE,                                   // cursor to display
+            self.cursors[cursor as usize],               // cursor to display
             xcb::CURRENT_TIME,                           // time
         );
     }
@@ -282,56 +652,93 @@ impl<'a> XConn<'a> {
         xcb::ungrab_pointer(self.conn, xcb::CURRENT_TIME);
     }
 
-    pub fn get_geometry(&self, window_id: XWindowID) -> Option<(i32, i32, i32, i32)> {
-        debug!("Getting geometry for window: {}", window_id);
-        match xcb::get_geometry(self.conn, window_id).get_reply() {
-            Ok(dimens) => return Some((dimens.x() as i32, dimens.y() as i32, dimens.width() as i32, dimens.height() as i32)),
-            Err(err) => {
-                warn!("Failed getting window geometry for {} ({}). Was window closed and not yet unmapped?", window_id, err);
-                return None;
-            },
-        }
-    }
-
-    pub fn get_window_attributes(&self, window_id: XWindowID) -> Option<xcb::GetWindowAttributesReply> {
+    pub fn get_window_attributes(&self, window_id: XWindowID) -> Result<xcb::GetWindowAttributesReply, XError> {
         debug!("Getting attributes for window: {}", window_id);
         match xcb::get_window_attributes(self.conn, window_id).get_reply() {
-            Ok(reply) => return Some(reply),
+            Ok(reply) => return Ok(reply),
             Err(err) => {
                 warn!("Failed getting attributes for window {} ({}). Was window closed and not yet unmapped?", window_id, err);
-                return None;
+                return Err(XError::from(err));
             }
         }
     }
 
-    pub fn get_wm_protocols(&self, window_id: XWindowID) -> Option<Vec<xcb::Atom>> {
-        debug!("Getting wm protocols for window: {}", window_id);
-        match icccm::get_wm_protocols(self.conn, window_id, self.atoms.WM_PROTOCOLS).get_reply() {
-            Ok(reply) => return Some(reply.atoms().to_owned()),
-            Err(_) => return None,
+    pub fn get_wm_window_type(&self, window_id: XWindowID) -> Result<Vec<xcb::Atom>, XError> {
+        debug!("Getting wm type for window: {}", window_id);
+        return Ok(ewmh::get_wm_window_type(self.conn, window_id).get_reply()?.atoms().to_owned());
+    }
+
+    pub fn get_wm_state(&self, window_id: XWindowID) -> Result<Vec<xcb::Atom>, XError> {
+        debug!("Getting wm state for window: {}", window_id);
+        return Ok(ewmh::get_wm_state(self.conn, window_id).get_reply()?.atoms().to_owned());
+    }
+
+    pub fn set_wm_state_atom(&self, window_id: XWindowID, state: xcb::Atom, enabled: bool) {
+        debug!("Setting wm state {} to {} for window: {}", state, enabled, window_id);
+
+        // Keep any other states the client has set
+        let mut states = self.get_wm_state(window_id).unwrap_or_default();
+        states.retain(|atom| *atom != state);
+        if enabled {
+            states.push(state);
         }
+        ewmh::set_wm_state(self.conn, window_id, &states);
     }
 
-    pub fn get_wm_window_type(&self, window_id: XWindowID) -> Option<Vec<xcb::Atom>> {
-        debug!("Getting wm type for window: {}", window_id);
-        match ewmh::get_wm_window_type(self.conn, window_id).get_reply() {
-            Ok(reply) => return Some(reply.atoms().to_owned()),
-            Err(_) => return None,
+    pub fn delete_icccm_wm_state(&self, window_id: XWindowID) {
+        debug!("Deleting icccm wm state for window: {}", window_id);
+
+        // Withdrawn windows have no WM_STATE. Errors are delivered via the event queue and handled in WM::run()
+        xcb::delete_property(self.conn, window_id, self.atoms.ICCCM_WM_STATE);
+    }
+
+    pub fn set_wm_desktop(&self, window_id: XWindowID, desktop: u32) {
+        debug!("Setting wm desktop {} for window: {}", desktop, window_id);
+        ewmh::set_wm_desktop(self.conn, window_id, desktop);
+    }
+
+    pub fn get_wm_class(&self, window_id: XWindowID) -> Result<(String, String), XError> {
+        debug!("Getting wm class for window: {}", window_id);
+
+        // Read WM_CLASS ourselves, as clients don't always set valid UTF-8
+        let reply = xcb::get_property(self.conn, false, window_id, xcb::ATOM_WM_CLASS, xcb::ATOM_STRING, 0, 1024).get_reply()?;
+
+        // Split into NUL-separated instance and class names
+        let mut names = reply.value::<u8>().split(|c| *c == 0).map(|name| String::from_utf8_lossy(name).into_owned());
+        let instance = names.next().unwrap_or_default();
+        let class = names.next().unwrap_or_default();
+        return Ok((instance, class));
+    }
+
+    pub fn get_wm_name(&self, window_id: XWindowID) -> Result<String, XError> {
+        debug!("Getting wm name for window: {}", window_id);
+
+        // Prefer the EWMH UTF-8 title, falling back to the ICCCM one
+        let reply = xcb::get_property(self.conn, false, window_id, self.conn.WM_NAME(), self.atoms.UTF8_STRING, 0, 1024).get_reply()?;
+        if reply.value_len() > 0 {
+            return Ok(String::from_utf8_lossy(reply.value::<u8>()).into_owned());
         }
+        let reply = xcb::get_property(self.conn, false, window_id, xcb::ATOM_WM_NAME, xcb::GET_PROPERTY_TYPE_ANY, 0, 1024).get_reply()?;
+        return Ok(String::from_utf8_lossy(reply.value::<u8>()).into_owned());
     }
 
-    pub fn query_pointer(&self, window_id: XWindowID) -> (i32, i32, XWindowID) {
+    pub fn query_pointer(&self, window_id: XWindowID) -> Result<(i32, i32, XWindowID), XError> {
         debug!("Querying pointer location for window: {}", window_id);
 
-        // We don't bother requesting check here as this is only ever used for root window
-        let pointer = xcb::query_pointer(self.conn, window_id).get_reply().expect("Querying window pointer location");
-        return (pointer.root_x() as i32, pointer.root_y() as i32, pointer.child())
+        // Query pointer and return root-relative position + child window
+        let pointer = xcb::query_pointer(self.conn, window_id).get_reply()?;
+        return Ok((pointer.root_x() as i32, pointer.root_y() as i32, pointer.child()));
     }
 
     #[cfg(debug_assertions)]
-    pub fn _get_atom_name(&self, atom: xcb::Atom) -> String {
+    pub fn _get_atom_name(&self, atom: xcb::Atom) -> Result<String, XError> {
         // don't debug log because it's being used for debug anyway
-        return xcb::get_atom_name(self.conn, atom).get_reply().expect("Getting atom name").name().to_owned();
+        return Ok(xcb::get_atom_name(self.conn, atom).get_reply()?.name().to_owned());
+    }
+
+    pub fn clean_mask(&self, state: u16) -> xcb::ModMask {
+        // Ignore any lock keys that are on, and pointer buttons held
+        return state as u32 & !self.lock_mask & !(xcb::KEY_BUT_MASK_BUTTON_1|xcb::KEY_BUT_MASK_BUTTON_2|xcb::KEY_BUT_MASK_BUTTON_3|xcb::KEY_BUT_MASK_BUTTON_4|xcb::KEY_BUT_MASK_BUTTON_5);
     }
 
     pub fn lookup_keysym(&self, event: &xcb::KeyPressEvent) -> (xcb::ModMask, xcb::Keysym) {
@@ -339,18 +746,196 @@ impl<'a> XConn<'a> {
         let keysym = self.key_syms.press_lookup_keysym(event, 0);
 
         // Create new tuple of (mod_mask, key_sym)
-        return (event.state() as u32, keysym);
+        return (self.clean_mask(event.state()), keysym);
+    }
+
+    fn wait_for_event(&self) -> Result<xcb::GenericEvent, XError> {
+        // Wait for next event. On None the connection has errored, so fetch that error
+        match self.conn.wait_for_event() {
+            Some(event) => return Ok(event),
+            None => {
+                self.conn.has_error()?;
+                return Err(XError::Connection(xcb::ConnError::Connection));
+            },
+        }
+    }
+
+    fn wait_for_event_timeout(&self, timeout: Duration) -> Result<Option<xcb::GenericEvent>, XError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Only a real timeout returns None
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            // Wait for the connection to become readable. Interrupted by a signal, just wait again
+            let mut fds = [libc::pollfd { fd: self.conn.as_raw_fd(), events: libc::POLLIN, revents: 0 }];
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), 1, (deadline - now).as_millis() as libc::c_int) };
+            if ready < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                return Err(XError::Connection(xcb::ConnError::Connection));
+            }
+            if ready <= 0 {
+                continue;
+            }
+
+            // Read it. May be only part of an event, in which case wait for the rest
+            if let Some(event) = self.conn.poll_for_event() {
+                return Ok(Some(event));
+            }
+            self.conn.has_error()?;
+        }
     }
 
-    pub fn next_event(&self) -> xcb::GenericEvent {
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<xcb::GenericEvent>, XError> {
+        // Return any events read ahead first
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok(Some(event));
+        }
+
         // Flush connection to ensure clean
         self.conn.flush();
 
-        // Check for queued, else wait for next
-        return if let Some(event) = self.conn.poll_for_queued_event() {
+        // Check for queued, else wait for next (up to timeout)
+        let mut event = if let Some(event) = self.conn.poll_for_queued_event() {
             event
+        } else if let Some(timeout) = timeout {
+            match self.wait_for_event_timeout(timeout)? {
+                Some(event) => event,
+                None => return Ok(None),
+            }
         } else {
-            self.conn.wait_for_event().expect("I/O error getting event from X server")
+            self.wait_for_event()?
         };
+
+        // Compress motion: skip to the latest of any consecutive queued MotionNotify events,
+        // holding back the first non-motion event to return next time
+        if event.response_type() & !0x80 == xcb::MOTION_NOTIFY {
+            while let Some(next) = self.conn.poll_for_queued_event() {
+                if next.response_type() & !0x80 == xcb::MOTION_NOTIFY {
+                    event = next;
+                } else {
+                    self.pending_events.push_back(next);
+                    break;
+                }
+            }
+        }
+
+        return Ok(Some(event));
+    }
+}
+
+impl<'a> XBackend for XConn<'a> {
+    fn map_window(&self, window_id: XWindowID) {
+        debug!("Mapping window: {}", window_id);
+
+        // Map window. Errors are delivered via the event queue and handled in WM::run()
+        xcb::map_window(self.conn, window_id);
+    }
+
+    fn unmap_window(&self, window_id: XWindowID) {
+        debug!("Unmapping window: {}", window_id);
+
+        // Unmap window. Errors are delivered via the event queue and handled in WM::run()
+        xcb::unmap_window(self.conn, window_id);
+    }
+
+    fn configure_window(&self, window_id: XWindowID, values: &[(u16, u32)]) {
+        debug!("Configuring window: {}", window_id);
+
+        // Configure window. Errors are delivered via the event queue and handled in WM::run()
+        xcb::configure_window(self.conn, window_id, values);
+    }
+
+    fn change_window_attributes(&self, window_id: XWindowID, values: &[(u32, u32)]) {
+        debug!("Changing window attributes: {}", window_id);
+
+        // Change window attributes. Errors are delivered via the event queue and handled in WM::run()
+        xcb::change_window_attributes(self.conn, window_id, values);
+    }
+
+    fn set_icccm_wm_state(&self, window_id: XWindowID, state: icccm::WmState) {
+        debug!("Setting icccm wm state {} for window: {}", state, window_id);
+
+        // State followed by icon window, which we don't use. Errors are delivered via the event queue and handled in WM::run()
+        xcb::change_property(self.conn, xcb::PROP_MODE_REPLACE as u8, window_id, self.atoms.ICCCM_WM_STATE, self.atoms.ICCCM_WM_STATE, 32, &[state as u32, xcb::NONE]);
+    }
+
+    fn set_input_focus(&self, window_id: XWindowID) {
+        debug!("Setting input focus window: {}", window_id);
+
+        // Set input focus on window. Errors are delivered via the event queue and handled in WM::run()
+        xcb::set_input_focus(self.conn, xcb::INPUT_FOCUS_POINTER_ROOT as u8, window_id, xcb::CURRENT_TIME);
+    }
+
+    fn grab_click_to_focus(&self, window_id: XWindowID) {
+        debug!("Grabbing click to focus for window: {}", window_id);
+
+        // Synchronous grab on any click, freezing the pointer until we replay it with allow_events().
+        // Errors are delivered via the event queue and handled in WM::run()
+        xcb::grab_button(
+            self.conn,
+            false,                              // owner events
+            window_id,                          // window id
+            xcb::EVENT_MASK_BUTTON_PRESS as u16, // button event mask
+            xcb::GRAB_MODE_SYNC as u8,          // pointer mode
+            xcb::GRAB_MODE_ASYNC as u8,         // keyboard mode
+            xcb::NONE,                          // confine pointer to window (or no confine)
+            xcb::NONE,                          // cursor to use
+            xcb::BUTTON_INDEX_ANY as u8,        // button to grab (any)
+            xcb::MOD_MASK_ANY as u16,           // Modifiers to grab mouse with (any)
+        );
+    }
+
+    fn ungrab_click_to_focus(&self, window_id: XWindowID) {
+        debug!("Ungrabbing click to focus for window: {}", window_id);
+
+        // Remove the grab. Errors are delivered via the event queue and handled in WM::run()
+        xcb::ungrab_button(self.conn, xcb::BUTTON_INDEX_ANY as u8, window_id, xcb::MOD_MASK_ANY as u16);
+    }
+
+    fn destroy_window(&self, window: &Window) {
+        debug!("Destroying window: {}", window.xwindow.id);
+
+        // Check how best to destroy window
+        if window.supports_protocol(&self.atoms.WM_DELETE_WINDOW) {
+            // Window support ICCCM method of WM_DELETE_WINDOW
+            debug!("Destroy window via ICCCM WM_DELETE_WINDOW");
+
+            // Create client message data
+            let msg_data = xcb::ClientMessageData::from_data32([self.atoms.WM_DELETE_WINDOW, xcb::CURRENT_TIME, 0, 0, 0]);
+
+            // Create event from message data
+            let event = xcb::ClientMessageEvent::new(32, window.xwindow.id, self.atoms.WM_PROTOCOLS, msg_data);
+
+            // Send the event!
+            xcb::send_event(
+                self.conn,                // connection
+                false,                    // propagate?
+                window.xwindow.id,        // destination window
+                xcb::EVENT_MASK_NO_EVENT, // event mask
+                &event,                   // event object
+            );
+        } else {
+            // Use plain-old X destroy window
+            debug!("Destroy window via xcb_destroy_window");
+            xcb::destroy_window(self.conn, window.xwindow.id);
+        }
+    }
+
+    fn get_geometry(&self, window_id: XWindowID) -> Result<(i32, i32, i32, i32), XError> {
+        debug!("Getting geometry for window: {}", window_id);
+        match xcb::get_geometry(self.conn, window_id).get_reply() {
+            Ok(dimens) => return Ok((dimens.x() as i32, dimens.y() as i32, dimens.width() as i32, dimens.height() as i32)),
+            Err(err) => {
+                warn!("Failed getting window geometry for {} ({}). Was window closed and not yet unmapped?", window_id, err);
+                return Err(XError::from(err));
+            },
+        }
+    }
+
+    fn get_wm_protocols(&self, window_id: XWindowID) -> Result<Vec<xcb::Atom>, XError> {
+        debug!("Getting wm protocols for window: {}", window_id);
+        return Ok(icccm::get_wm_protocols(self.conn, window_id, self.atoms.WM_PROTOCOLS).get_reply()?.atoms().to_owned());
     }
 }
@@ -0,0 +1,184 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use x11::keysym;
+use xcb_util::keysyms::KeySymbols;
+
+// How long we wait for the window manager to react before failing
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Poll condition until true or timed out
+pub fn wait_until<F: FnMut() -> bool>(mut cond: F) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        if cond() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    return false;
+}
+
+fn intern_atom(conn: &xcb::Connection, name: &str) -> xcb::Atom {
+    return xcb::intern_atom(conn, false, name).get_reply().expect("Interning atom").atom();
+}
+
+pub struct Session {
+    // Nested X server and window manager processes
+    xvfb: Child,
+    wm: Child,
+
+    // Display name e.g. ":1"
+    pub display: String,
+
+    // Our own connection, used for inspecting state + faking input
+    pub conn: xcb::Connection,
+}
+
+impl Session {
+    pub fn start() -> Self {
+        // Start Xvfb, letting it pick a free display and write it to stdout. These tests
+        // are only run when asked for, so a missing Xvfb is a failure, not a skip
+        let mut xvfb = Command::new("Xvfb")
+            .args(["-displayfd", "1", "-screen", "0", "1024x768x24", "-nolisten", "tcp"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Spawning Xvfb (is it installed?)");
+
+        // Read the display number, written once the server is ready
+        let mut line = String::new();
+        BufReader::new(xvfb.stdout.take().unwrap()).read_line(&mut line).expect("Reading Xvfb display number");
+        let display = format!(":{}", line.trim());
+
+        // Start the window manager against the nested server
+        let wm = Command::new(env!("CARGO_BIN_EXE_afwm"))
+            .env("DISPLAY", &display)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Spawning afwm");
+
+        // Connect ourselves
+        let (conn, _) = xcb::Connection::connect(Some(&display)).expect("Connecting to Xvfb");
+
+        let session = Self { xvfb, wm, display, conn };
+
+        // Wait until the window manager owns the WM_S0 selection, i.e. has finished registering
+        let selection = intern_atom(&session.conn, "WM_S0");
+        assert!(wait_until(|| {
+            xcb::get_selection_owner(&session.conn, selection).get_reply().unwrap().owner() != xcb::NONE
+        }), "afwm did not start");
+
+        return session;
+    }
+
+    pub fn spawn_client(&self) -> Client {
+        return Client::new(&self.display);
+    }
+
+    pub fn focused(&self) -> xcb::Window {
+        return xcb::get_input_focus(&self.conn).get_reply().unwrap().focus();
+    }
+
+    // Top-most managed window, the last viewable child of root
+    pub fn top(&self) -> xcb::Window {
+        let root = self.conn.get_setup().roots().next().unwrap().root();
+        let tree = xcb::query_tree(&self.conn, root).get_reply().unwrap();
+        return *tree.children().iter().rev().find(|id| self.is_viewable(**id)).unwrap_or(&xcb::NONE);
+    }
+
+    pub fn is_viewable(&self, window_id: xcb::Window) -> bool {
+        match xcb::get_window_attributes(&self.conn, window_id).get_reply() {
+            Ok(attr) => return attr.map_state() as u32 == xcb::MAP_STATE_VIEWABLE,
+            Err(_) => return false,
+        }
+    }
+
+    // Fake a key press + release of keysym, with modifier keysyms held down
+    pub fn press(&self, mods: &[xcb::Keysym], key: xcb::Keysym) {
+        let key_syms = KeySymbols::new(&self.conn);
+        let code = |sym| key_syms.get_keycode(sym).next().expect("Keysym has no keycode");
+
+        for m in mods {
+            xcb::test::fake_input(&self.conn, xcb::KEY_PRESS, code(*m), xcb::CURRENT_TIME, xcb::NONE, 0, 0, 0);
+        }
+        xcb::test::fake_input(&self.conn, xcb::KEY_PRESS, code(key), xcb::CURRENT_TIME, xcb::NONE, 0, 0, 0);
+        xcb::test::fake_input(&self.conn, xcb::KEY_RELEASE, code(key), xcb::CURRENT_TIME, xcb::NONE, 0, 0, 0);
+        for m in mods.iter().rev() {
+            xcb::test::fake_input(&self.conn, xcb::KEY_RELEASE, code(*m), xcb::CURRENT_TIME, xcb::NONE, 0, 0, 0);
+        }
+
+        self.conn.flush();
+    }
+
+    // Press key with the afwm MODKEY held
+    pub fn press_mod(&self, key: xcb::Keysym) {
+        self.press(&[keysym::XK_Super_L], key);
+    }
+
+    // Press key with the afwm MODKEY + shift held
+    pub fn press_mod_shift(&self, key: xcb::Keysym) {
+        self.press(&[keysym::XK_Super_L, keysym::XK_Shift_L], key);
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.wm.kill();
+        let _ = self.wm.wait();
+        let _ = self.xvfb.kill();
+        let _ = self.xvfb.wait();
+    }
+}
+
+pub struct Client {
+    // Each client has its own connection, like a real application
+    conn: xcb::Connection,
+    pub id: xcb::Window,
+
+    wm_protocols: xcb::Atom,
+    wm_delete_window: xcb::Atom,
+}
+
+impl Client {
+    fn new(display: &str) -> Self {
+        let (conn, screen_idx) = xcb::Connection::connect(Some(display)).expect("Connecting client");
+        let screen = conn.get_setup().roots().nth(screen_idx as usize).unwrap();
+
+        // Keep away from the pointer (screen centre) so EnterNotify doesn't move focus around
+        let id = conn.generate_id();
+        xcb::create_window(
+            &conn, xcb::COPY_FROM_PARENT as u8, id, screen.root(),
+            10, 10, 200, 100, 0,
+            xcb::WINDOW_CLASS_INPUT_OUTPUT as u16, screen.root_visual(),
+            &[],
+        );
+
+        // Advertise WM_DELETE_WINDOW support
+        let wm_protocols = intern_atom(&conn, "WM_PROTOCOLS");
+        let wm_delete_window = intern_atom(&conn, "WM_DELETE_WINDOW");
+        xcb::change_property(&conn, xcb::PROP_MODE_REPLACE as u8, id, wm_protocols, xcb::ATOM_ATOM, 32, &[wm_delete_window]);
+
+        xcb::map_window(&conn, id);
+        conn.flush();
+
+        return Self { conn, id, wm_protocols, wm_delete_window };
+    }
+
+    // Wait for a WM_DELETE_WINDOW client message from the window manager
+    pub fn wait_for_delete(&self) -> bool {
+        return wait_until(|| {
+            while let Some(event) = self.conn.poll_for_event() {
+                if event.response_type() & !0x80 == xcb::CLIENT_MESSAGE {
+                    let event: &xcb::ClientMessageEvent = unsafe { xcb::cast_event(&event) };
+                    if event.type_() == self.wm_protocols && event.data().data32()[0] == self.wm_delete_window {
+                        return true;
+                    }
+                }
+            }
+            return false;
+        });
+    }
+}
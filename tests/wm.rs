@@ -0,0 +1,80 @@
+// Integration tests running afwm against a nested Xvfb server. These need Xvfb installed,
+// so are ignored by default. Run with: cargo test -- --ignored
+mod common;
+
+use common::{wait_until, Session};
+use x11::keysym;
+
+#[test]
+#[ignore = "needs Xvfb, run with --ignored"]
+fn maps_and_focuses_new_window() {
+    let session = Session::start();
+
+    let client = session.spawn_client();
+    assert!(wait_until(|| session.is_viewable(client.id)));
+    assert!(wait_until(|| session.focused() == client.id));
+}
+
+#[test]
+#[ignore = "needs Xvfb, run with --ignored"]
+fn focus_cycle_follows_window_order() {
+    let session = Session::start();
+
+    // New windows go to the front of Windows, so this leaves it ordered: third, second, first
+    let first = session.spawn_client();
+    assert!(wait_until(|| session.focused() == first.id));
+    let second = session.spawn_client();
+    assert!(wait_until(|| session.focused() == second.id));
+    let third = session.spawn_client();
+    assert!(wait_until(|| session.focused() == third.id));
+
+    // Cycling brings the last window in Windows to the front, focused and on top. Only the
+    // order above visits them as first, second, third (a plain swap of the two most recent wouldn't)
+    for client in &[&first, &second, &third] {
+        session.press_mod(keysym::XK_Tab);
+        assert!(wait_until(|| session.focused() == client.id && session.top() == client.id));
+    }
+}
+
+#[test]
+#[ignore = "needs Xvfb, run with --ignored"]
+fn goto_workspace_hides_and_restores_windows() {
+    let session = Session::start();
+
+    let client = session.spawn_client();
+    assert!(wait_until(|| session.is_viewable(client.id)));
+
+    session.press_mod(keysym::XK_2);
+    assert!(wait_until(|| !session.is_viewable(client.id)));
+
+    session.press_mod(keysym::XK_1);
+    assert!(wait_until(|| session.is_viewable(client.id)));
+    assert!(wait_until(|| session.focused() == client.id));
+}
+
+#[test]
+#[ignore = "needs Xvfb, run with --ignored"]
+fn send_window_to_other_workspace() {
+    let session = Session::start();
+
+    let client = session.spawn_client();
+    assert!(wait_until(|| session.focused() == client.id));
+
+    session.press_mod_shift(keysym::XK_2);
+    assert!(wait_until(|| !session.is_viewable(client.id)));
+
+    session.press_mod(keysym::XK_2);
+    assert!(wait_until(|| session.is_viewable(client.id)));
+}
+
+#[test]
+#[ignore = "needs Xvfb, run with --ignored"]
+fn close_sends_wm_delete_window() {
+    let session = Session::start();
+
+    let client = session.spawn_client();
+    assert!(wait_until(|| session.focused() == client.id));
+
+    session.press_mod_shift(keysym::XK_c);
+    assert!(client.wait_for_delete());
+}